@@ -0,0 +1,104 @@
+use anyhow::Result;
+use base64::{engine::general_purpose, Engine as _};
+use http::header::{HeaderName, HeaderValue, AUTHORIZATION, COOKIE};
+use http::HeaderMap;
+
+use crate::graph_data::AuthMode;
+use crate::template::resolve_secret;
+
+/// Names used for the API key when the auth mode needs one and none is configured
+const DEFAULT_AUTH_HEADER: &str = "X-API-Key";
+const DEFAULT_AUTH_QUERY_PARAM: &str = "api_key";
+const DEFAULT_AUTH_COOKIE: &str = "token";
+
+/// Credentials and extra headers sent when connecting to a remote source
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct AuthConfig {
+    /// The API key, or an `env:`/`file:` reference to it
+    pub api_key: Option<String>,
+    pub mode: AuthMode,
+    pub username: Option<String>,
+    /// Header, query parameter or cookie name carrying the API key
+    pub key_name: Option<String>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl AuthConfig {
+    /// The API key, read from the environment or a file if it is a secret reference
+    pub fn api_key(&self) -> Result<Option<String>> {
+        self.api_key.as_deref().map(resolve_secret).transpose()
+    }
+
+    /// Add the credentials to a URL, returning it with the headers to send
+    pub fn apply(&self, url: &str) -> Result<(String, HeaderMap)> {
+        let api_key = self.api_key()?;
+        let mut headers = HeaderMap::new();
+
+        let url = match (self.mode, api_key.as_deref()) {
+            (AuthMode::Query, Some(key)) => {
+                let param = self.key_name.as_deref().unwrap_or(DEFAULT_AUTH_QUERY_PARAM);
+                let separator = if url.contains('?') { '&' } else { '?' };
+                format!(
+                    "{}{}{}={}",
+                    url,
+                    separator,
+                    encode_query_component(param),
+                    encode_query_component(key)
+                )
+            }
+            _ => url.to_string(),
+        };
+
+        if let Some(key) = api_key {
+            match self.mode {
+                AuthMode::Bearer => {
+                    headers.insert(AUTHORIZATION, format!("Bearer {}", key).parse()?);
+                }
+                AuthMode::Basic => {
+                    let username = self.username.as_deref().unwrap_or_default();
+                    let encoded = general_purpose::STANDARD.encode(format!("{}:{}", username, key));
+                    headers.insert(AUTHORIZATION, format!("Basic {}", encoded).parse()?);
+                }
+                AuthMode::Header => {
+                    let name = self.key_name.as_deref().unwrap_or(DEFAULT_AUTH_HEADER);
+                    headers.insert(HeaderName::from_bytes(name.as_bytes())?, key.parse()?);
+                }
+                AuthMode::Cookie => {
+                    let name = self.key_name.as_deref().unwrap_or(DEFAULT_AUTH_COOKIE);
+                    headers.append(COOKIE, format!("{}={}", name, key).parse()?);
+                }
+                AuthMode::Query => {}
+            }
+        }
+
+        for (name, value) in &self.headers {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+
+        Ok((url, headers))
+    }
+
+    /// The same headers without the API key, for protocols that authenticate in-band
+    pub fn without_credentials(&self) -> Self {
+        Self {
+            api_key: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Percent-encode a query string component
+fn encode_query_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
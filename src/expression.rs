@@ -0,0 +1,478 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::graph_data::MetricType;
+
+/// A value an expression reads
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Input {
+    /// A built-in metric such as `cpu_temp` or `net_download`
+    Metric(MetricType),
+    /// The latest value of a named key, e.g. `ws:outside_temp`
+    Named(String),
+}
+
+/// Value of a key computed from other metrics, e.g. `net_download + net_upload`
+/// or `max(cpu_temp, gpu_temp) - ws:room_temp`
+///
+/// Supports `+ - * / ^`, parentheses and the functions `min`, `max`, `avg`,
+/// `sum`, `abs`, `clamp(x, low, high)` and `coalesce(a, b...)`. Metrics are
+/// named like the metric types with or without underscores, named keys as
+/// `<kind>:<name>` with kind one of `ws`, `sse`, `local`, `push` or `expr`.
+///
+/// An input without a value makes the result unavailable, except in `min`,
+/// `max`, `avg` and `sum`, which use the inputs that have one, and `coalesce`,
+/// which picks the first input that has one. Division by zero is unavailable too.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    root: Node,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Number(f64),
+    Input(Input),
+    Negate(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Function {
+    Min,
+    Max,
+    Avg,
+    Sum,
+    Abs,
+    Clamp,
+    Coalesce,
+}
+
+impl Function {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            "avg" => Some(Function::Avg),
+            "sum" => Some(Function::Sum),
+            "abs" => Some(Function::Abs),
+            "clamp" => Some(Function::Clamp),
+            "coalesce" => Some(Function::Coalesce),
+            _ => None,
+        }
+    }
+
+    /// Check the number of arguments
+    fn check_arity(self, name: &str, count: usize) -> Result<()> {
+        let valid = match self {
+            Function::Abs => count == 1,
+            Function::Clamp => count == 3,
+            Function::Min | Function::Max | Function::Avg | Function::Sum | Function::Coalesce => {
+                count >= 1
+            }
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(anyhow!("Wrong number of arguments to {}()", name))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Operator(char),
+    Open,
+    Close,
+    Comma,
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Self> {
+        let tokens = tokenize(text)?;
+        if tokens.is_empty() {
+            return Err(anyhow!("Expression is empty"));
+        }
+
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let root = parser.expression()?;
+        match parser.peek() {
+            None => Ok(Self { root }),
+            Some(token) => Err(anyhow!("Unexpected {:?} in expression", token)),
+        }
+    }
+
+    /// Every input the expression reads, each once
+    pub fn inputs(&self) -> Vec<Input> {
+        let mut inputs = Vec::new();
+        self.root.collect_inputs(&mut inputs);
+        inputs
+    }
+
+    /// Compute the value from the inputs that have one
+    pub fn evaluate(&self, values: &HashMap<Input, f32>) -> Option<f32> {
+        self.root
+            .evaluate(values)
+            .filter(|value| value.is_finite())
+            .map(|value| value as f32)
+    }
+}
+
+impl Node {
+    fn collect_inputs(&self, inputs: &mut Vec<Input>) {
+        match self {
+            Node::Number(_) => {}
+            Node::Input(input) => {
+                if !inputs.contains(input) {
+                    inputs.push(input.clone());
+                }
+            }
+            Node::Negate(node) => node.collect_inputs(inputs),
+            Node::Binary(_, left, right) => {
+                left.collect_inputs(inputs);
+                right.collect_inputs(inputs);
+            }
+            Node::Call(_, args) => args.iter().for_each(|arg| arg.collect_inputs(inputs)),
+        }
+    }
+
+    fn evaluate(&self, values: &HashMap<Input, f32>) -> Option<f64> {
+        match self {
+            Node::Number(number) => Some(*number),
+            Node::Input(input) => values.get(input).map(|&value| value as f64),
+            Node::Negate(node) => node.evaluate(values).map(|value| -value),
+            Node::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(values)?, right.evaluate(values)?);
+                match operator {
+                    Operator::Add => Some(left + right),
+                    Operator::Subtract => Some(left - right),
+                    Operator::Multiply => Some(left * right),
+                    Operator::Divide => (right != 0.0).then(|| left / right),
+                    Operator::Power => Some(left.powf(right)),
+                }
+            }
+            Node::Call(function, args) => {
+                let mut available = args.iter().filter_map(|arg| arg.evaluate(values));
+                match function {
+                    Function::Min => available.reduce(f64::min),
+                    Function::Max => available.reduce(f64::max),
+                    Function::Sum => available.reduce(|sum, value| sum + value),
+                    Function::Avg => {
+                        let values: Vec<f64> = available.collect();
+                        (!values.is_empty())
+                            .then(|| values.iter().sum::<f64>() / values.len() as f64)
+                    }
+                    Function::Coalesce => available.next(),
+                    Function::Abs => args[0].evaluate(values).map(f64::abs),
+                    Function::Clamp => {
+                        let value = args[0].evaluate(values)?;
+                        let (low, high) = (args[1].evaluate(values)?, args[2].evaluate(values)?);
+                        (low <= high).then(|| value.clamp(low, high))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Recursive descent over the tokens, lowest precedence first
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(anyhow!("Expected {:?} but found {:?}", expected, token)),
+            None => Err(anyhow!(
+                "Expected {:?} at the end of the expression",
+                expected
+            )),
+        }
+    }
+
+    /// Sums and differences
+    fn expression(&mut self) -> Result<Node> {
+        let mut node = self.term()?;
+        while let Some(Token::Operator(op @ ('+' | '-'))) = self.peek() {
+            let operator = if *op == '+' {
+                Operator::Add
+            } else {
+                Operator::Subtract
+            };
+            self.next();
+            node = Node::Binary(operator, Box::new(node), Box::new(self.term()?));
+        }
+        Ok(node)
+    }
+
+    /// Products and quotients
+    fn term(&mut self) -> Result<Node> {
+        let mut node = self.unary()?;
+        while let Some(Token::Operator(op @ ('*' | '/'))) = self.peek() {
+            let operator = if *op == '*' {
+                Operator::Multiply
+            } else {
+                Operator::Divide
+            };
+            self.next();
+            node = Node::Binary(operator, Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node> {
+        if let Some(Token::Operator('-')) = self.peek() {
+            self.next();
+            return Ok(Node::Negate(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    /// Powers are right associative and bind tighter than negation on their left
+    fn power(&mut self) -> Result<Node> {
+        let base = self.atom()?;
+        if let Some(Token::Operator('^')) = self.peek() {
+            self.next();
+            return Ok(Node::Binary(
+                Operator::Power,
+                Box::new(base),
+                Box::new(self.unary()?),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Node> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Node::Number(number)),
+            Some(Token::Open) => {
+                let node = self.expression()?;
+                self.expect(Token::Close)?;
+                Ok(node)
+            }
+            Some(Token::Name(name)) if self.peek() == Some(&Token::Open) => {
+                let function =
+                    Function::parse(&name).ok_or_else(|| anyhow!("Unknown function {}()", name))?;
+                self.next();
+
+                let mut args = vec![self.expression()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.next();
+                    args.push(self.expression()?);
+                }
+                self.expect(Token::Close)?;
+
+                function.check_arity(&name, args.len())?;
+                Ok(Node::Call(function, args))
+            }
+            Some(Token::Name(name)) => Ok(Node::Input(parse_input(&name)?)),
+            Some(token) => Err(anyhow!("Unexpected {:?} in expression", token)),
+            None => Err(anyhow!("Expression ends too early")),
+        }
+    }
+}
+
+/// Resolve a name to a named key (`kind:name`) or a metric type
+fn parse_input(name: &str) -> Result<Input> {
+    if let Some((kind, key)) = name.split_once(':') {
+        return match kind {
+            "ws" | "sse" | "local" | "push" | "expr" if !key.is_empty() => {
+                Ok(Input::Named(name.to_string()))
+            }
+            _ => Err(anyhow!("Unknown source reference '{}'", name)),
+        };
+    }
+
+    // Metric types are named like their settings value, `cpu_temp` reads as `cputemp`
+    serde_json::from_value(Value::String(name.replace('_', "").to_lowercase()))
+        .map(Input::Metric)
+        .map_err(|_| anyhow!("Unknown metric '{}'", name))
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '+' | '-' | '*' | '/' | '^' => Token::Operator(c),
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_ascii_digit() || c == '.') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let number = &text[start..end];
+                Token::Number(
+                    number
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid number '{}'", number))?,
+                )
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                // Names of named keys may contain a `:` after the kind and `.` after that
+                let mut end = start + c.len_utf8();
+                let mut named = false;
+                while let Some(&(i, c)) = chars.peek() {
+                    let allowed =
+                        c.is_alphanumeric() || c == '_' || c == ':' && !named || named && c == '.';
+                    if !allowed {
+                        break;
+                    }
+                    named |= c == ':';
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                Token::Name(text[start..end].to_string())
+            }
+            c => return Err(anyhow!("Unexpected '{}' in expression", c)),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str, values: &[(Input, f32)]) -> Option<f32> {
+        Expression::parse(text)
+            .unwrap()
+            .evaluate(&values.iter().cloned().collect())
+    }
+
+    fn named(name: &str) -> Input {
+        Input::Named(name.to_string())
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        assert_eq!(eval("1 + 2 * 3", &[]), Some(7.0));
+        assert_eq!(eval("(1 + 2) * 3", &[]), Some(9.0));
+        assert_eq!(eval("10 - 4 - 3", &[]), Some(3.0));
+        assert_eq!(eval("12 / 3 / 2", &[]), Some(2.0));
+        assert_eq!(eval("2 * 3 ^ 2", &[]), Some(18.0));
+    }
+
+    #[test]
+    fn unary_minus_and_powers() {
+        assert_eq!(eval("-2 ^ 2", &[]), Some(-4.0));
+        assert_eq!(eval("(-2) ^ 2", &[]), Some(4.0));
+        assert_eq!(eval("2 ^ -1", &[]), Some(0.5));
+        assert_eq!(eval("2 ^ 3 ^ 2", &[]), Some(512.0));
+        assert_eq!(eval("3 - -2", &[]), Some(5.0));
+    }
+
+    #[test]
+    fn named_keys_stop_at_operators() {
+        assert_eq!(
+            tokenize("ws:foo - ws:bar").unwrap(),
+            vec![
+                Token::Name("ws:foo".to_string()),
+                Token::Operator('-'),
+                Token::Name("ws:bar".to_string()),
+            ]
+        );
+
+        let expression = Expression::parse("ws:foo-ws:bar").unwrap();
+        assert_eq!(expression.inputs(), vec![named("ws:foo"), named("ws:bar")]);
+        assert_eq!(
+            eval(
+                "ws:foo - ws:bar",
+                &[(named("ws:foo"), 5.0), (named("ws:bar"), 2.0)]
+            ),
+            Some(3.0)
+        );
+    }
+
+    #[test]
+    fn metrics_are_named_like_their_types() {
+        let expression = Expression::parse("cpu_temp + cputemp + NET_DOWNLOAD").unwrap();
+        assert_eq!(
+            expression.inputs(),
+            vec![
+                Input::Metric(MetricType::CpuTemp),
+                Input::Metric(MetricType::NetDownload),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        let error = |text| Expression::parse(text).unwrap_err().to_string();
+        assert!(error("cpu_tmp + 1").contains("Unknown metric"));
+        assert!(error("median(1, 2)").contains("Unknown function"));
+        assert!(error("abs(1, 2)").contains("Wrong number of arguments"));
+        assert!(error("clamp(1, 2)").contains("Wrong number of arguments"));
+        assert!(error("foo:bar").contains("Unknown source reference"));
+        assert!(Expression::parse("").is_err());
+        assert!(Expression::parse("(1 + 2").is_err());
+        assert!(Expression::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn aggregates_skip_missing_inputs() {
+        let values = [(named("ws:a"), 2.0), (named("ws:c"), 6.0)];
+        let all = "ws:a, ws:b, ws:c";
+        assert_eq!(eval(&format!("min({})", all), &values), Some(2.0));
+        assert_eq!(eval(&format!("max({})", all), &values), Some(6.0));
+        assert_eq!(eval(&format!("avg({})", all), &values), Some(4.0));
+        assert_eq!(eval(&format!("sum({})", all), &values), Some(8.0));
+        assert_eq!(eval("coalesce(ws:b, ws:c, ws:a)", &values), Some(6.0));
+
+        assert_eq!(eval("avg(ws:b, ws:d)", &values), None);
+        assert_eq!(eval("coalesce(ws:b, ws:d)", &values), None);
+        assert_eq!(eval("ws:a + ws:b", &values), None);
+        assert_eq!(eval("abs(ws:b)", &values), None);
+    }
+
+    #[test]
+    fn division_by_zero_is_unavailable() {
+        assert_eq!(eval("1 / 0", &[]), None);
+        assert_eq!(eval("0 / 0", &[]), None);
+        assert_eq!(
+            eval("ws:a / ws:b", &[(named("ws:a"), 1.0), (named("ws:b"), 0.0)]),
+            None
+        );
+        assert_eq!(eval("(-1) ^ 0.5", &[]), None);
+        assert_eq!(eval("clamp(5, 0, 3)", &[]), Some(3.0));
+    }
+}
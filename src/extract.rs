@@ -0,0 +1,395 @@
+use anyhow::{anyhow, Result};
+use jsonpath_rust::parser::model::JpQuery;
+use jsonpath_rust::parser::parse_json_path;
+use jsonpath_rust::query::js_path_process;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::graph_data::{BinaryFormat, DecimalSeparator, TextFormat};
+
+/// Selects a single value out of a JSON message
+///
+/// Selectors starting with `/` are JSON Pointers (`/data/cpu/temp`), selectors
+/// starting with `$` are JSONPath expressions (`$.sensors[?@.name == 'cpu'].value`)
+/// and anything else is treated as a dotted field path (`data.cpu.temp`).
+#[derive(Clone, Debug)]
+pub enum ValueSelector {
+    Pointer(String),
+    Path(JpQuery),
+}
+
+impl ValueSelector {
+    pub fn parse(selector: &str) -> Result<Self> {
+        let selector = selector.trim();
+
+        if selector.is_empty() {
+            return Err(anyhow!("Value selector is empty"));
+        }
+
+        if selector.starts_with('$') {
+            let path = parse_json_path(selector)
+                .map_err(|e| anyhow!("Invalid JSONPath '{}': {}", selector, e))?;
+            return Ok(ValueSelector::Path(path));
+        }
+
+        if selector.starts_with('/') {
+            return Ok(ValueSelector::Pointer(selector.to_string()));
+        }
+
+        // Dotted field path, converted to a JSON Pointer with the required escaping
+        let pointer = selector
+            .split('.')
+            .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+            .collect();
+        Ok(ValueSelector::Pointer(pointer))
+    }
+
+    /// Find the JSON node this selector points at
+    pub fn select<'a>(&self, json: &'a Value) -> Option<&'a Value> {
+        match self {
+            ValueSelector::Pointer(pointer) => json.pointer(pointer),
+            ValueSelector::Path(path) => js_path_process(path, json)
+                .ok()?
+                .into_iter()
+                .next()
+                .map(|found| found.val),
+        }
+    }
+
+    /// Select a node and interpret it as a number
+    pub fn select_number(&self, json: &Value) -> Option<f32> {
+        self.select(json).and_then(as_number)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FilterOp {
+    Eq,
+    Ne,
+}
+
+/// Accepts or rejects whole messages based on one of their fields
+///
+/// Written as `<selector> == <value>` or `<selector> != <value>`, e.g.
+/// `type == "event"` or `/payload/id == 3`. The right-hand side is parsed as
+/// JSON, falling back to a plain string when it isn't valid JSON.
+#[derive(Clone, Debug)]
+pub struct MessageFilter {
+    selector: ValueSelector,
+    op: FilterOp,
+    expected: Value,
+}
+
+impl MessageFilter {
+    pub fn parse(filter: &str) -> Result<Self> {
+        let (index, op) = find_operator(filter).ok_or_else(|| {
+            anyhow!(
+                "Invalid message filter '{}': expected '<field> == <value>' or '<field> != <value>'",
+                filter
+            )
+        })?;
+        let (lhs, rhs) = (&filter[..index], &filter[index + 2..]);
+
+        let rhs = rhs.trim();
+        let expected = serde_json::from_str(rhs).unwrap_or_else(|_| Value::String(rhs.to_string()));
+
+        Ok(Self {
+            selector: ValueSelector::parse(lhs)?,
+            op,
+            expected,
+        })
+    }
+
+    pub fn matches(&self, json: &Value) -> bool {
+        let actual = self.selector.select(json);
+        let equal = actual.is_some_and(|actual| values_equal(actual, &self.expected));
+
+        match self.op {
+            FilterOp::Eq => equal,
+            FilterOp::Ne => !equal,
+        }
+    }
+}
+
+/// Find the first `==` or `!=` outside brackets and quotes
+///
+/// JSONPath selectors carry their own comparisons inside `[?...]` filters,
+/// and the value may contain either operator after the split.
+fn find_operator(filter: &str) -> Option<(usize, FilterOp)> {
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut chars = filter.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '[') => depth += 1,
+            (None, ']') => depth = depth.saturating_sub(1),
+            (None, '=') if depth == 0 && next == Some('=') => return Some((i, FilterOp::Eq)),
+            (None, '!') if depth == 0 && next == Some('=') => return Some((i, FilterOp::Ne)),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Compare JSON values, treating numbers by value so `1` matches `1.0`
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => a == b,
+    }
+}
+
+/// Reads values out of plain-text messages such as `42.5 °C` or `temp=42,5`
+#[derive(Clone, Debug)]
+pub struct TextExtractor {
+    pattern: Option<Regex>,
+    separator: DecimalSeparator,
+}
+
+impl TextExtractor {
+    /// Build the extractor for a text format, `None` for JSON
+    pub fn new(
+        format: TextFormat,
+        pattern: Option<&str>,
+        separator: DecimalSeparator,
+    ) -> Result<Option<Self>> {
+        let pattern = match format {
+            TextFormat::Json => return Ok(None),
+            TextFormat::Number => None,
+            TextFormat::Regex => {
+                let pattern =
+                    pattern.ok_or_else(|| anyhow!("Regex text format needs a pattern"))?;
+                Some(
+                    Regex::new(pattern)
+                        .map_err(|e| anyhow!("Invalid pattern '{}': {}", pattern, e))?,
+                )
+            }
+        };
+
+        Ok(Some(Self { pattern, separator }))
+    }
+
+    /// Extract the value and, if the message has one, its unit
+    ///
+    /// With a pattern, the number comes from the `value` group (or the first
+    /// group, or the whole match) and the unit from a `unit` group. Without one,
+    /// the message is a number and whatever follows it is the unit.
+    pub fn extract(&self, text: &str) -> Option<(f32, Option<String>)> {
+        let Some(pattern) = &self.pattern else {
+            let (value, rest) = split_number(text, self.separator)?;
+            return Some((value, Some(rest.to_string()).filter(|u| !u.is_empty())));
+        };
+
+        let captures = pattern.captures(text)?;
+        let number = captures
+            .name("value")
+            .or_else(|| captures.get(1))
+            .or_else(|| captures.get(0))?;
+        let (value, rest) = split_number(number.as_str(), self.separator)?;
+
+        let unit = match captures.name("unit") {
+            Some(unit) => Some(unit.as_str().trim().to_string()),
+            None => Some(rest.to_string()),
+        };
+        Some((value, unit.filter(|u| !u.is_empty())))
+    }
+}
+
+/// Parse the number at the start of `text`, returning it with the rest of the text
+///
+/// Grouping characters (the other separator, spaces and apostrophes) are skipped
+/// when they split the integer part into groups of three digits, so `1,234.5 W`
+/// and `1 234,5 W` both read as 1234.5. Anywhere else they end the number, so
+/// `42,5` with a decimal point reads as 42.
+fn split_number(text: &str, separator: DecimalSeparator) -> Option<(f32, &str)> {
+    let text = text.trim();
+    let (decimal, grouping) = match separator {
+        DecimalSeparator::Point => ('.', ','),
+        DecimalSeparator::Comma => (',', '.'),
+    };
+
+    let mut number = String::new();
+    let mut end = 0;
+    // Digits since the start of the integer part or the last grouping character
+    let mut group_digits = 0;
+    let mut grouped = false;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        let next_is_digit = next.is_some_and(|next| next.is_ascii_digit());
+        let has_digits = number.chars().any(|c| c.is_ascii_digit());
+
+        match c {
+            '0'..='9' => {
+                number.push(c);
+                group_digits += 1;
+            }
+            '+' | '-' if number.is_empty() || number.ends_with('e') => number.push(c),
+            'e' | 'E'
+                if has_digits
+                    && !number.contains('e')
+                    && (next_is_digit || matches!(next, Some('+' | '-'))) =>
+            {
+                number.push('e')
+            }
+            c if c == decimal && !number.contains(['.', 'e']) => number.push('.'),
+            c if (c == grouping || matches!(c, ' ' | '\'' | '\u{a0}' | '\u{202f}'))
+                && !number.contains(['.', 'e'])
+                && (1..=3).contains(&group_digits)
+                && (!grouped || group_digits == 3)
+                && is_digit_group(&text[i + c.len_utf8()..]) =>
+            {
+                group_digits = 0;
+                grouped = true;
+            }
+            _ => break,
+        }
+
+        end = i + c.len_utf8();
+    }
+
+    let value = number.parse::<f32>().ok()?;
+    Some((value, text[end..].trim()))
+}
+
+/// Whether `text` starts with exactly three digits
+fn is_digit_group(text: &str) -> bool {
+    let digits = text.bytes().take_while(u8::is_ascii_digit).count();
+    digits == 3
+}
+
+/// Interpret a selected JSON node as a number
+///
+/// Besides plain numbers this accepts numeric strings (many APIs send `"21.5"`)
+/// and booleans, which are graphed as 0 or 1.
+pub fn as_number(json: &Value) -> Option<f32> {
+    match json {
+        Value::Number(n) => n.as_f64().map(|v| v as f32),
+        Value::String(s) => s.trim().parse::<f32>().ok(),
+        Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        _ => None,
+    }
+}
+
+/// Extract a numeric value from a JSON message when no selector is configured
+/// This is a simple heuristic - looks for first numeric field
+pub fn guess_value(json: &Value) -> Option<f32> {
+    match json {
+        Value::Number(n) => n.as_f64().map(|v| v as f32),
+        Value::Object(map) => {
+            // Try common field names first
+            for key in &["value", "data", "result", "temperature", "temp", "load"] {
+                if let Some(val) = map.get(*key) {
+                    if let Some(num) = guess_value(val) {
+                        return Some(num);
+                    }
+                }
+            }
+            // If not found, try first numeric value in any field
+            for (_key, val) in map.iter() {
+                if let Some(num) = guess_value(val) {
+                    return Some(num);
+                }
+            }
+            None
+        }
+        Value::Array(arr) => {
+            // Try first element
+            arr.first().and_then(guess_value)
+        }
+        _ => None,
+    }
+}
+
+/// Decode a binary frame into JSON so it goes through the same selectors as text
+pub fn decode_binary(format: BinaryFormat, data: &[u8]) -> Result<Value> {
+    match format {
+        BinaryFormat::None => Err(anyhow!("Binary frames are not enabled")),
+        BinaryFormat::MessagePack => Ok(rmp_serde::from_slice(data)?),
+        BinaryFormat::Cbor => Ok(ciborium::from_reader(data)?),
+        BinaryFormat::F32Le => decode_numbers(data, |b| f32::from_le_bytes(b) as f64),
+        BinaryFormat::F32Be => decode_numbers(data, |b| f32::from_be_bytes(b) as f64),
+        BinaryFormat::F64Le => decode_numbers(data, f64::from_le_bytes),
+        BinaryFormat::F64Be => decode_numbers(data, f64::from_be_bytes),
+        BinaryFormat::I32Le => decode_numbers(data, |b| i32::from_le_bytes(b) as f64),
+        BinaryFormat::I32Be => decode_numbers(data, |b| i32::from_be_bytes(b) as f64),
+    }
+}
+
+/// Decode a frame of fixed-size numbers: a single number stays a number,
+/// several become an array that selectors can index (`/1`)
+fn decode_numbers<const N: usize>(data: &[u8], decode: impl Fn([u8; N]) -> f64) -> Result<Value> {
+    if data.is_empty() || !data.len().is_multiple_of(N) {
+        return Err(anyhow!(
+            "Binary frame of {} bytes is not a whole number of {}-byte values",
+            data.len(),
+            N
+        ));
+    }
+
+    let mut numbers: Vec<Value> = data
+        .chunks_exact(N)
+        .map(|chunk| {
+            let bytes = chunk.try_into().expect("chunk has exactly N bytes");
+            serde_json::Number::from_f64(decode(bytes)).map_or(Value::Null, Value::Number)
+        })
+        .collect();
+
+    Ok(if numbers.len() == 1 {
+        numbers.remove(0)
+    } else {
+        Value::Array(numbers)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn filters_split_on_the_first_operator() {
+        let filter = MessageFilter::parse("type!=a==b").unwrap();
+        assert!(filter.matches(&json!({"type": "a"})));
+        assert!(!filter.matches(&json!({"type": "a==b"})));
+
+        let filter = MessageFilter::parse("expr == x!=y").unwrap();
+        assert!(filter.matches(&json!({"expr": "x!=y"})));
+        assert!(!filter.matches(&json!({"expr": "x"})));
+    }
+
+    #[test]
+    fn filters_skip_operators_inside_json_path_filters() {
+        let filter = MessageFilter::parse("$.sensors[?@.name == 'cpu'].value == 5").unwrap();
+        let message = |value| json!({"sensors": [{"name": "gpu", "value": 5}, {"name": "cpu", "value": value}]});
+        assert!(filter.matches(&message(5)));
+        assert!(!filter.matches(&message(6)));
+
+        let filter = MessageFilter::parse("$.sensors[?@.name != 'gpu'].value != 5").unwrap();
+        assert!(filter.matches(&message(6)));
+        assert!(!filter.matches(&message(5)));
+    }
+
+    #[test]
+    fn grouping_needs_groups_of_three() {
+        let point = DecimalSeparator::Point;
+        let comma = DecimalSeparator::Comma;
+        assert_eq!(split_number("1,234.5 W", point), Some((1234.5, "W")));
+        assert_eq!(split_number("1 234,5 W", comma), Some((1234.5, "W")));
+        assert_eq!(split_number("12'345'678", point), Some((12345678.0, "")));
+        assert_eq!(split_number("42,5", point), Some((42.0, ",5")));
+        assert_eq!(split_number("1,2345", point), Some((1.0, ",2345")));
+        assert_eq!(split_number("1234,567", point), Some((1234.0, ",567")));
+        assert_eq!(split_number("0.5 1", point), Some((0.5, "1")));
+    }
+}
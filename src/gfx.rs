@@ -2,140 +2,315 @@ use ab_glyph::{FontRef, PxScale};
 use anyhow::Result;
 use base64::{engine::general_purpose, Engine as _};
 use image::{Rgba, RgbaImage};
-use imageproc::drawing::draw_text_mut;
+use imageproc::drawing::{draw_text_mut, text_size};
 use std::io::Cursor;
 
+use crate::source::ConnectionState;
+
 const ICON_SIZE: u32 = 144;
 const GRAPH_PADDING: u32 = 10;
 const TITLE_HEIGHT: u32 = 35;
+const STATUS_DOT_RADIUS: i32 = 4;
+const LEGEND_FONT_SIZE: f32 = 11.0;
+const LEGEND_SWATCH_SIZE: u32 = 6;
+/// Longest legend label in characters, longer ones are cut
+const LEGEND_LABEL_CHARS: usize = 8;
+
+/// Width of the plot area in pixels, the most points a graph can show
+pub const PLOT_WIDTH: u32 = ICON_SIZE - GRAPH_PADDING * 2;
+
+pub const DEFAULT_NORMAL_COLOR: Rgba<u8> = Rgba([0, 255, 0, 255]); // Green
+pub const DEFAULT_WARNING_COLOR: Rgba<u8> = Rgba([255, 0, 0, 255]); // Red
 
-/// Color scheme for graph based on threshold
+/// A threshold above which values are drawn in another color
 #[derive(Clone, Copy)]
+pub struct Level {
+    pub threshold: f32,
+    pub color: Rgba<u8>,
+}
+
+/// Color scheme for graph based on threshold levels
+#[derive(Clone)]
 pub struct ColorScheme {
     pub normal_color: Rgba<u8>,
-    pub warning_color: Rgba<u8>,
+    /// Levels ordered by threshold, e.g. warning then critical
+    pub levels: Vec<Level>,
 }
 
 impl Default for ColorScheme {
     fn default() -> Self {
         Self {
-            normal_color: Rgba([0, 255, 0, 255]),  // Green
-            warning_color: Rgba([255, 0, 0, 255]), // Red
+            normal_color: DEFAULT_NORMAL_COLOR,
+            levels: Vec::new(),
+        }
+    }
+}
+
+impl ColorScheme {
+    /// Color of a level, 0 being the normal color
+    pub fn color(&self, level: usize) -> Rgba<u8> {
+        match level.checked_sub(1) {
+            Some(index) => self
+                .levels
+                .get(index)
+                .or(self.levels.last())
+                .map_or(self.normal_color, |level| level.color),
+            None => self.normal_color,
         }
     }
 }
 
+/// A series plotted alongside the main data points
+#[derive(Clone)]
+pub struct Series {
+    /// Values ending with the main data points' last one; a series that
+    /// started later has fewer
+    pub data_points: Vec<f32>,
+    pub color: Rgba<u8>,
+    pub label: String,
+    /// Bounds of an independent axis, `None` to share the graph's
+    pub range: Option<(f32, f32)>,
+}
+
 /// Configuration for rendering a graph
 pub struct GraphConfig {
     pub data_points: Vec<f32>,
+    /// Lowest and highest sample behind each data point when the history is
+    /// downsampled, empty otherwise
+    pub envelope: Vec<(f32, f32)>,
     pub max_value: f32,
     pub min_value: f32,
-    pub threshold: Option<f32>,
+    /// Map values logarithmically between the bounds
+    pub log_scale: bool,
     pub color_scheme: ColorScheme,
+    /// Active level of the color scheme, tracked with hysteresis as values come in
+    pub level: usize,
     pub title: String,
+    /// Connection state of a remote source, `None` for local sensors
+    pub connection_state: Option<ConnectionState>,
+    /// The source is connected but hasn't delivered a value within its timeout
+    pub stale: bool,
+    /// Extra series drawn on the graph, with a legend when there are any
+    pub series: Vec<Series>,
+    /// Stack the series as filled areas on top of the data points instead of
+    /// overlaying their lines; independent axes don't apply then
+    pub stacked: bool,
+    /// Legend label of the main data points
+    pub label: String,
 }
 
 impl Default for GraphConfig {
     fn default() -> Self {
         Self {
             data_points: Vec::new(),
+            envelope: Vec::new(),
             max_value: 100.0,
             min_value: 0.0,
-            threshold: None,
+            log_scale: false,
             color_scheme: ColorScheme::default(),
+            level: 0,
             title: String::new(),
+            connection_state: None,
+            stale: false,
+            series: Vec::new(),
+            stacked: false,
+            label: String::new(),
         }
     }
 }
 
+impl GraphConfig {
+    /// Position of a value between the bounds from 0 (min) to 1 (max), or
+    /// `None` if the bounds are empty
+    fn scale(&self, value: f32) -> Option<f32> {
+        self.scale_between(value, (self.min_value, self.max_value))
+    }
+
+    /// Like `scale`, for an axis with other bounds
+    fn scale_between(&self, value: f32, (min_value, max_value): (f32, f32)) -> Option<f32> {
+        let (min, max, value) = if self.log_scale {
+            // A log axis needs a positive floor, three decades below the top unless set
+            let max = max_value.max(f32::MIN_POSITIVE);
+            let min = if min_value > 0.0 {
+                min_value
+            } else {
+                max / 1000.0
+            };
+            (min.log10(), max.log10(), value.max(min).log10())
+        } else {
+            (min_value, max_value, value)
+        };
+
+        let range = max - min;
+        (range > 0.0).then(|| ((value - min) / range).clamp(0.0, 1.0))
+    }
+}
+
 /// Generate a timeseries graph image with gradient fill
 pub fn generate_graph(config: &GraphConfig) -> Result<RgbaImage> {
     let mut img = RgbaImage::from_pixel(ICON_SIZE, ICON_SIZE, Rgba([0, 0, 0, 255]));
 
     if config.data_points.is_empty() {
+        draw_status_indicator(&mut img, config);
         return Ok(img);
     }
 
     // Title is always shown at the top
     let graph_height = ICON_SIZE - GRAPH_PADDING * 2 - TITLE_HEIGHT;
-    let graph_width = ICON_SIZE - GRAPH_PADDING * 2;
+    let graph_width = PLOT_WIDTH;
 
-    // Determine if we're in warning state (current value exceeds threshold)
-    let current_value = config.data_points.last().copied().unwrap_or(0.0);
-    let is_warning = config.threshold.map(|t| current_value > t).unwrap_or(false);
-
-    let line_color = if is_warning {
-        config.color_scheme.warning_color
-    } else {
-        config.color_scheme.normal_color
-    };
+    let line_color = config.color_scheme.color(config.level);
 
     // Draw title at the top center
     draw_title(&mut img, &config.title, &line_color);
 
-    // Normalize data points to graph coordinates
-    let points = normalize_points(
-        &config.data_points,
-        config.min_value,
-        config.max_value,
-        graph_width,
-        graph_height,
-    );
+    // Every series is spread over the slots of the main data points
+    let slots = config.data_points.len();
+    let normalize = |data: &[f32], range: Option<(f32, f32)>| {
+        let scale = |value| match range {
+            Some(range) => config.scale_between(value, range),
+            None => config.scale(value),
+        };
+        normalize_points(data, slots, scale, graph_width, graph_height)
+    };
 
-    // Draw gradient fill under the line
-    draw_gradient_fill(
-        &mut img,
-        &points,
-        GRAPH_PADDING,
-        GRAPH_PADDING + TITLE_HEIGHT,
-        graph_height,
-        &line_color,
-    );
+    if config.stacked && !config.series.is_empty() {
+        // Each series fills the band between the running total below it and its own
+        let layers: Vec<&[f32]> = std::iter::once(config.data_points.as_slice())
+            .chain(
+                config
+                    .series
+                    .iter()
+                    .map(|series| series.data_points.as_slice()),
+            )
+            .collect();
+        let colors = std::iter::once(line_color).chain(config.series.iter().map(|s| s.color));
+        let mut bottom = vec![(0, graph_height), (graph_width, graph_height)];
+
+        for (totals, color) in stack(&layers).iter().zip(colors) {
+            let top = normalize(totals, None);
+            draw_band(
+                &mut img,
+                &bottom,
+                &top,
+                GRAPH_PADDING,
+                GRAPH_PADDING + TITLE_HEIGHT,
+                &Rgba([color[0], color[1], color[2], 140]),
+            );
+            draw_connected_line(
+                &mut img,
+                &top,
+                GRAPH_PADDING,
+                GRAPH_PADDING + TITLE_HEIGHT,
+                &color,
+            );
+            bottom = top;
+        }
+    } else {
+        // Normalize data points to graph coordinates
+        let points = normalize(&config.data_points, None);
 
-    // Draw the connected line
-    draw_connected_line(
-        &mut img,
-        &points,
-        GRAPH_PADDING,
-        GRAPH_PADDING + TITLE_HEIGHT,
-        &line_color,
-    );
+        // Draw gradient fill under the line
+        draw_gradient_fill(
+            &mut img,
+            &points,
+            GRAPH_PADDING,
+            GRAPH_PADDING + TITLE_HEIGHT,
+            graph_height,
+            &line_color,
+        );
+
+        // Draw the min-max band so spikes averaged out of the line stay visible
+        if config.envelope.len() == config.data_points.len() {
+            let (lows, highs): (Vec<f32>, Vec<f32>) = config.envelope.iter().copied().unzip();
+
+            draw_band(
+                &mut img,
+                &normalize(&lows, None),
+                &normalize(&highs, None),
+                GRAPH_PADDING,
+                GRAPH_PADDING + TITLE_HEIGHT,
+                &Rgba([line_color[0], line_color[1], line_color[2], 90]),
+            );
+        }
+
+        // Draw the connected line
+        draw_connected_line(
+            &mut img,
+            &points,
+            GRAPH_PADDING,
+            GRAPH_PADDING + TITLE_HEIGHT,
+            &line_color,
+        );
+
+        // Extra series are drawn as plain lines over the main one
+        for series in &config.series {
+            draw_connected_line(
+                &mut img,
+                &normalize(&series.data_points, series.range),
+                GRAPH_PADDING,
+                GRAPH_PADDING + TITLE_HEIGHT,
+                &series.color,
+            );
+        }
+    }
+
+    if !config.series.is_empty() {
+        let entries: Vec<(&str, Rgba<u8>)> = std::iter::once((config.label.as_str(), line_color))
+            .chain(config.series.iter().map(|s| (s.label.as_str(), s.color)))
+            .collect();
+        draw_legend(&mut img, &entries);
+    }
+
+    draw_status_indicator(&mut img, config);
 
     Ok(img)
 }
 
-/// Normalize data points to graph coordinates
+/// Running totals of layers, each ending with the first layer's last value;
+/// the last total is the sum of every layer
+pub fn stack(layers: &[&[f32]]) -> Vec<Vec<f32>> {
+    let slots = layers.first().map_or(0, |layer| layer.len());
+    let mut totals = vec![0.0; slots];
+
+    layers
+        .iter()
+        .map(|layer| {
+            let layer = &layer[layer.len().saturating_sub(slots)..];
+            for (total, value) in totals[slots - layer.len()..].iter_mut().zip(layer) {
+                *total += value;
+            }
+            totals.clone()
+        })
+        .collect()
+}
+
+/// Normalize data points to graph coordinates, placing them in the last of
+/// `slots` evenly spaced positions
 fn normalize_points(
     data: &[f32],
-    min_val: f32,
-    max_val: f32,
+    slots: usize,
+    scale: impl Fn(f32) -> Option<f32>,
     width: u32,
     height: u32,
 ) -> Vec<(u32, u32)> {
-    let range = max_val - min_val;
-    if range == 0.0 {
-        return data
-            .iter()
-            .enumerate()
-            .map(|(i, _)| {
-                let x = (i as f32 / (data.len() - 1).max(1) as f32 * width as f32) as u32;
-                (x, height / 2)
-            })
-            .collect();
-    }
+    let data = &data[data.len().saturating_sub(slots)..];
+    let first_slot = slots - data.len();
 
     data.iter()
         .enumerate()
         .map(|(i, &val)| {
-            let x = if data.len() > 1 {
-                (i as f32 / (data.len() - 1) as f32 * width as f32) as u32
+            let x = if slots > 1 {
+                ((first_slot + i) as f32 / (slots - 1) as f32 * width as f32) as u32
             } else {
                 width / 2
             };
 
-            // Invert Y because image coordinates go top to bottom
-            let normalized = ((val - min_val) / range).clamp(0.0, 1.0);
-            let y = height - (normalized * height as f32) as u32;
+            // Invert Y because image coordinates go top to bottom, empty ranges draw mid-height
+            let y = match scale(val) {
+                Some(normalized) => height - (normalized * height as f32) as u32,
+                None => height / 2,
+            };
 
             (x, y)
         })
@@ -259,6 +434,36 @@ fn draw_gradient_fill(
     }
 }
 
+/// Draw a translucent band between the low and high points
+fn draw_band(
+    img: &mut RgbaImage,
+    lows: &[(u32, u32)],
+    highs: &[(u32, u32)],
+    offset_x: u32,
+    offset_y: u32,
+    band_color: &Rgba<u8>,
+) {
+    let (Some(&(min_x, _)), Some(&(max_x, _))) = (highs.first(), highs.last()) else {
+        return;
+    };
+
+    for x in min_x..=max_x {
+        // Highs are nearer the top of the image
+        let top = interpolate_y_at_x(highs, x);
+        let bottom = interpolate_y_at_x(lows, x);
+
+        for py in top..=bottom {
+            let actual_x = x + offset_x;
+            let actual_y = py + offset_y;
+
+            if actual_x < ICON_SIZE && actual_y < ICON_SIZE {
+                let bg = img.get_pixel(actual_x, actual_y);
+                img.put_pixel(actual_x, actual_y, blend_colors(*bg, *band_color));
+            }
+        }
+    }
+}
+
 /// Interpolate Y value at a given X coordinate
 fn interpolate_y_at_x(points: &[(u32, u32)], x: u32) -> u32 {
     // Find the two points that bracket this x value
@@ -316,13 +521,15 @@ fn blend_colors(bg: Rgba<u8>, fg: Rgba<u8>) -> Rgba<u8> {
     Rgba([r, g, b, a])
 }
 
+/// The embedded DejaVu Sans font
+fn load_font() -> Option<FontRef<'static>> {
+    FontRef::try_from_slice(include_bytes!("../fonts/DejaVuSans.ttf")).ok()
+}
+
 /// Draw title text centered at the top of the image with larger font
 fn draw_title(img: &mut RgbaImage, title: &str, color: &Rgba<u8>) {
-    // Use embedded DejaVu Sans font
-    let font_data = include_bytes!("../fonts/DejaVuSans.ttf");
-    let font = match FontRef::try_from_slice(font_data) {
-        Ok(f) => f,
-        Err(_) => return, // Silently fail if font not available
+    let Some(font) = load_font() else {
+        return; // Silently fail if font not available
     };
 
     let scale = PxScale::from(25.0); // Larger font size
@@ -336,6 +543,85 @@ fn draw_title(img: &mut RgbaImage, title: &str, color: &Rgba<u8>) {
     draw_text_mut(img, text_color, x_offset, y_offset, scale, &font, title);
 }
 
+/// Draw a color swatch and label per series along the bottom edge, wrapping
+/// onto more rows when they don't fit
+fn draw_legend(img: &mut RgbaImage, entries: &[(&str, Rgba<u8>)]) {
+    let Some(font) = load_font() else {
+        return;
+    };
+    let scale = PxScale::from(LEGEND_FONT_SIZE);
+    let row_height = LEGEND_FONT_SIZE as u32 + 2;
+    let max_width = ICON_SIZE - 4;
+
+    // Lay entries out in rows first so the legend can sit flush with the bottom
+    let mut rows: Vec<Vec<(String, Rgba<u8>, u32)>> = vec![Vec::new()];
+    let mut row_width = 0;
+    for &(label, color) in entries {
+        let label: String = label.chars().take(LEGEND_LABEL_CHARS).collect();
+        let width = LEGEND_SWATCH_SIZE + 2 + text_size(scale, &font, &label).0;
+
+        if row_width > 0 && row_width + width > max_width {
+            rows.push(Vec::new());
+            row_width = 0;
+        }
+        row_width += width + 5;
+        if let Some(row) = rows.last_mut() {
+            row.push((label, color, width));
+        }
+    }
+
+    // A dark backdrop keeps the labels readable over the plot
+    let top = ICON_SIZE.saturating_sub(rows.len() as u32 * row_height + 2);
+    for y in top..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let bg = img.get_pixel(x, y);
+            img.put_pixel(x, y, blend_colors(*bg, Rgba([0, 0, 0, 170])));
+        }
+    }
+
+    for (row_index, row) in rows.iter().enumerate() {
+        let y = top + 1 + row_index as u32 * row_height;
+        let mut x = 2;
+
+        for (label, color, width) in row {
+            let swatch_y = y + (row_height - LEGEND_SWATCH_SIZE) / 2;
+            for dy in 0..LEGEND_SWATCH_SIZE {
+                for dx in 0..LEGEND_SWATCH_SIZE {
+                    draw_point(img, x + dx, swatch_y + dy, color);
+                }
+            }
+
+            let text_x = (x + LEGEND_SWATCH_SIZE + 2) as i32;
+            draw_text_mut(img, *color, text_x, y as i32, scale, &font, label);
+            x += width + 5;
+        }
+    }
+}
+
+/// Draw a small dot in the top-right corner when a remote source isn't delivering
+/// live data: amber while connecting, red when disconnected, magenta when the
+/// server rejected initialization, grey when stale
+fn draw_status_indicator(img: &mut RgbaImage, config: &GraphConfig) {
+    let color = match config.connection_state {
+        Some(ConnectionState::Connecting) => Rgba([255, 191, 0, 255]),
+        Some(ConnectionState::Disconnected) => Rgba([255, 0, 0, 255]),
+        Some(ConnectionState::Failed) => Rgba([255, 0, 255, 255]),
+        _ if config.stale => Rgba([128, 128, 128, 255]),
+        _ => return,
+    };
+
+    let cx = ICON_SIZE as i32 - STATUS_DOT_RADIUS - 4;
+    let cy = STATUS_DOT_RADIUS + 4;
+
+    for dy in -STATUS_DOT_RADIUS..=STATUS_DOT_RADIUS {
+        for dx in -STATUS_DOT_RADIUS..=STATUS_DOT_RADIUS {
+            if dx * dx + dy * dy <= STATUS_DOT_RADIUS * STATUS_DOT_RADIUS {
+                draw_point(img, (cx + dx) as u32, (cy + dy) as u32, &color);
+            }
+        }
+    }
+}
+
 /// Convert image to base64 data URI
 pub fn image_to_data_uri(img: &RgbaImage) -> Result<String> {
     let mut buffer = Vec::new();
@@ -351,17 +637,12 @@ pub fn generate_gauge(config: &GraphConfig) -> Result<RgbaImage> {
     let mut img = RgbaImage::from_pixel(ICON_SIZE, ICON_SIZE, Rgba([0, 0, 0, 255]));
 
     if config.data_points.is_empty() {
+        draw_status_indicator(&mut img, config);
         return Ok(img);
     }
 
     let current_value = config.data_points.last().copied().unwrap_or(0.0);
-    let is_warning = config.threshold.map(|t| current_value > t).unwrap_or(false);
-
-    let text_color = if is_warning {
-        config.color_scheme.warning_color
-    } else {
-        config.color_scheme.normal_color
-    };
+    let text_color = config.color_scheme.color(config.level);
 
     // Draw title at the top
     draw_title(&mut img, &config.title, &text_color);
@@ -378,85 +659,40 @@ pub fn generate_gauge(config: &GraphConfig) -> Result<RgbaImage> {
     let arc_range = end_angle - start_angle + 2.0 * std::f32::consts::PI; // Handle wrap around
 
     // Calculate the percentage for current value
-    let range = config.max_value - config.min_value;
-    let percentage = if range > 0.0 {
-        ((current_value - config.min_value) / range).clamp(0.0, 1.0)
-    } else {
-        0.0
-    };
-
-    // Calculate threshold percentage
-    let threshold_percentage = config
-        .threshold
-        .map(|t| {
-            if range > 0.0 {
-                ((t - config.min_value) / range).clamp(0.0, 1.0)
-            } else {
-                0.8
-            }
-        })
-        .unwrap_or(0.8);
-
+    let percentage = config.scale(current_value).unwrap_or(0.0);
     let filled_angle = start_angle + (percentage * arc_range);
-    let threshold_angle = start_angle + (threshold_percentage * arc_range);
-
-    // Draw background arc with threshold coloring
-    // First, draw the normal zone (0% to threshold)
-    let normal_bg_color = Rgba([
-        config.color_scheme.normal_color[0] / 3,
-        config.color_scheme.normal_color[1] / 3,
-        config.color_scheme.normal_color[2] / 3,
-        180,
-    ]);
-    draw_thick_arc(
-        &mut img,
-        center_x,
-        center_y,
-        inner_radius,
-        outer_radius,
-        start_angle,
-        threshold_angle,
-        &normal_bg_color,
+
+    // Draw background arc with a dimmed zone per level, starting with the
+    // normal zone up to the first threshold
+    let zone_starts = std::iter::once(0.0).chain(
+        config
+            .color_scheme
+            .levels
+            .iter()
+            .map(|level| config.scale(level.threshold).unwrap_or(1.0)),
     );
+    let zone_ends = zone_starts.clone().skip(1).chain(std::iter::once(1.0));
 
-    // Then, draw the warning zone (threshold to 100%)
-    if config.threshold.is_some() {
-        let warning_bg_color = Rgba([
-            config.color_scheme.warning_color[0] / 3,
-            config.color_scheme.warning_color[1] / 3,
-            config.color_scheme.warning_color[2] / 3,
-            180,
-        ]);
-        draw_thick_arc(
-            &mut img,
-            center_x,
-            center_y,
-            inner_radius,
-            outer_radius,
-            threshold_angle,
-            end_angle,
-            &warning_bg_color,
-        );
-    } else {
-        // No threshold, draw remaining arc in normal color
+    for (level, (start, end)) in zone_starts.zip(zone_ends).enumerate() {
+        if end <= start {
+            continue;
+        }
+
+        let color = config.color_scheme.color(level);
         draw_thick_arc(
             &mut img,
             center_x,
             center_y,
             inner_radius,
             outer_radius,
-            threshold_angle,
-            end_angle,
-            &normal_bg_color,
+            start_angle + start * arc_range,
+            start_angle + end * arc_range,
+            &Rgba([color[0] / 3, color[1] / 3, color[2] / 3, 180]),
         );
     }
 
-    // Draw filled arc (progress) - determine color based on threshold
-    let fill_color = if percentage > threshold_percentage && config.threshold.is_some() {
-        config.color_scheme.warning_color
-    } else {
-        config.color_scheme.normal_color
-    };
+    // Draw filled arc (progress) in the active level's color
+    let fill_color = text_color;
 
     draw_thick_arc(
         &mut img,
@@ -469,6 +705,8 @@ pub fn generate_gauge(config: &GraphConfig) -> Result<RgbaImage> {
         &fill_color,
     );
 
+    draw_status_indicator(&mut img, config);
+
     Ok(img)
 }
 
@@ -1,11 +1,36 @@
-use crate::gfx::{ColorScheme, GraphConfig};
+use crate::auth::AuthConfig;
+use crate::expression::Expression;
+use crate::extract::{MessageFilter, TextExtractor, ValueSelector};
+use crate::gfx::{self, ColorScheme, GraphConfig, Level, Series, PLOT_WIDTH};
+use crate::history::{Bucket, History};
+use crate::levels::LevelTracker;
+use crate::local::{LocalClient, LocalConfig};
+use crate::push::{PushServer, DEFAULT_PUSH_PORT};
+use crate::smoothing::Smoother;
+use crate::source::{ConnectionState, Subscription, SubscriptionConfig};
+use crate::sse::{SseClient, SseConfig};
+use crate::store;
+use crate::template::expand_home;
+use crate::tls::TlsConfig;
+use crate::units::UnitFormat;
 use crate::websocket::{WebSocketClient, WebSocketConfig};
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-const MAX_DATA_POINTS: usize = 60;
+const DEFAULT_SAMPLE_INTERVAL_SECS: u64 = 1;
+const DEFAULT_WINDOW_SECS: u64 = 60;
+const DEFAULT_SMOOTHING_ALPHA: f32 = 0.3;
+const DEFAULT_SMOOTHING_SAMPLES: usize = 5;
+const DEFAULT_PRECISION: usize = 1;
+/// Margin below a threshold before leaving its level, as a share of the threshold
+const DEFAULT_HYSTERESIS_RATIO: f32 = 0.02;
+const DEFAULT_DWELL_SECS: u64 = 2;
+
+/// Series a graph draws besides the key's own value
+const MAX_EXTRA_SERIES: usize = 3;
+const SERIES_COLORS: [&str; MAX_EXTRA_SERIES] = ["#00bfff", "#ffbf00", "#ff4fd8"];
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 #[serde(rename_all = "lowercase")]
@@ -13,6 +38,216 @@ pub enum DataSource {
     #[default]
     LmSensors,
     WebSocket,
+    /// Server-Sent Events (`text/event-stream`)
+    Sse,
+    /// Unix domain socket or named pipe
+    Local,
+    /// Values pushed to the plugin's localhost endpoint
+    Push,
+    /// Computed from other metrics and named keys
+    Expression,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WebSocketMode {
+    #[default]
+    Generic,
+    HomeAssistant,
+}
+
+/// How a local source reads its path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LocalMode {
+    /// Connect to a socket a daemon listens on
+    #[default]
+    Connect,
+    /// Create the socket and accept any number of writers
+    Listen,
+    /// Read a named pipe, created if missing
+    Fifo,
+}
+
+/// How the API key is sent when connecting to a remote source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// `Authorization: Bearer <key>`
+    #[default]
+    Bearer,
+    /// `Authorization: Basic`, with the API key as the password
+    Basic,
+    /// Custom header carrying the key
+    Header,
+    /// Query parameter appended to the URL
+    Query,
+    /// Cookie carrying the key
+    Cookie,
+}
+
+/// How binary WebSocket frames are decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryFormat {
+    /// Binary frames are ignored
+    #[default]
+    None,
+    MessagePack,
+    Cbor,
+    /// Raw numbers; frames holding several become an array
+    F32Le,
+    F32Be,
+    F64Le,
+    F64Be,
+    I32Le,
+    I32Be,
+}
+
+/// How text messages are turned into values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TextFormat {
+    /// JSON, read with the value selector
+    #[default]
+    Json,
+    /// The message is a number, optionally followed by a unit ("42.5 °C")
+    Number,
+    /// A regular expression captures the number from the message
+    Regex,
+}
+
+/// How extra series are drawn with the key's own value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SeriesLayout {
+    /// Lines drawn over each other
+    #[default]
+    Overlay,
+    /// Filled areas stacked on top of the key's own value
+    Stacked,
+}
+
+/// A series plotted alongside the key's own value
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct SeriesSettings {
+    /// What to plot, written like an expression source, e.g. `net_upload`
+    pub expression: String,
+    pub label: Option<String>,
+    /// Hex color, taken from a palette when unset
+    pub color: Option<String>,
+    /// Scale the series to its own range instead of the shared axis
+    pub own_axis: bool,
+}
+
+/// A threshold level, values above it are drawn in its color
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct LevelSettings {
+    pub threshold: f32,
+    /// Hex color, the warning color when unset
+    pub color: Option<String>,
+}
+
+/// How the value axis bounds are chosen, `min_value`/`max_value` override either bound
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleMode {
+    /// The metric's default range
+    #[default]
+    Fixed,
+    /// Lowest to highest value in the window
+    Fit,
+    /// Fit with some headroom, rounded out to round numbers
+    Nice,
+    /// From zero up to a ceiling that only ever grows
+    Grow,
+}
+
+/// Filter applied to readings before they are plotted or shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Smoothing {
+    #[default]
+    None,
+    /// Exponential moving average
+    Ema,
+    /// Mean of the last samples
+    Average,
+    /// Median of the last samples, rejects outliers
+    Median,
+}
+
+/// Decimal separator used by text sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DecimalSeparator {
+    /// `1,234.5`
+    #[default]
+    Point,
+    /// `1.234,5`
+    Comma,
+}
+
+/// One message sent while initializing a WebSocket connection
+///
+/// `expect` and `fail` are message filters (`id == 1`, `type == "auth_ok"`).
+/// Steps with neither are sent without waiting for a reply unless
+/// `wait_for_reply` is set, and steps without a message only wait. Older
+/// settings stored plain strings, which load as steps waiting for any reply
+/// as they used to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(from = "InitStepSetting")]
+pub struct InitStep {
+    pub message: String,
+    /// Response that completes this step
+    pub expect: Option<String>,
+    /// Response that aborts initialization
+    pub fail: Option<String>,
+    pub timeout_secs: Option<u64>,
+    /// Wait for a reply of any kind when there is no `expect` or `fail`
+    pub wait_for_reply: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InitStepSetting {
+    Message(String),
+    Step {
+        #[serde(default)]
+        message: String,
+        expect: Option<String>,
+        fail: Option<String>,
+        timeout_secs: Option<u64>,
+        #[serde(default)]
+        wait_for_reply: bool,
+    },
+}
+
+impl From<InitStepSetting> for InitStep {
+    fn from(setting: InitStepSetting) -> Self {
+        match setting {
+            InitStepSetting::Message(message) => InitStep {
+                message,
+                wait_for_reply: true,
+                ..Default::default()
+            },
+            InitStepSetting::Step {
+                message,
+                expect,
+                fail,
+                timeout_secs,
+                wait_for_reply,
+            } => InitStep {
+                message,
+                expect,
+                fail,
+                timeout_secs,
+                wait_for_reply,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
@@ -23,7 +258,7 @@ pub enum VisualizationType {
     Gauge,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum MetricType {
     CpuTemp,
@@ -31,6 +266,7 @@ pub enum MetricType {
     CpuLoad,
     GpuTemp,
     GpuLoad,
+    GpuPower,
     MotherboardTemp,
     NvmeTemp,
     SystemFan,
@@ -60,6 +296,7 @@ impl MetricType {
             | MetricType::RamTemp => 120.0,
             MetricType::CpuLoad | MetricType::GpuLoad | MetricType::RamUsage => 100.0,
             MetricType::SystemFan => 3000.0,
+            MetricType::GpuPower => 300.0, // W
             MetricType::CpuVoltage => 2.0,
             MetricType::DiskWrite | MetricType::DiskRead => 500.0, // MB/s
             MetricType::NetDownload | MetricType::NetUpload => 125.0, // MB/s (1 Gbps)
@@ -85,6 +322,7 @@ impl MetricType {
             MetricType::CpuLoad => "CPU Load",
             MetricType::GpuTemp => "GPU Temp",
             MetricType::GpuLoad => "GPU Load",
+            MetricType::GpuPower => "GPU Power",
             MetricType::MotherboardTemp => "Motherboard",
             MetricType::NvmeTemp => "NVMe Temp",
             MetricType::SystemFan => "System Fan",
@@ -98,7 +336,8 @@ impl MetricType {
         }
     }
 
-    pub fn value_suffix(&self) -> &str {
+    /// Unit of the values read for this metric
+    pub fn unit(&self) -> &str {
         match self {
             MetricType::CpuTemp
             | MetricType::CpuPackageTemp
@@ -107,10 +346,11 @@ impl MetricType {
             | MetricType::NvmeTemp
             | MetricType::RamTemp => "°C",
             MetricType::CpuLoad | MetricType::GpuLoad | MetricType::RamUsage => "%",
-            MetricType::SystemFan => " RPM",
+            MetricType::SystemFan => "RPM",
             MetricType::CpuVoltage => "V",
-            MetricType::DiskWrite | MetricType::DiskRead => " MB/s",
-            MetricType::NetDownload | MetricType::NetUpload => " MB/s",
+            MetricType::GpuPower => "W",
+            MetricType::DiskWrite | MetricType::DiskRead => "MB/s",
+            MetricType::NetDownload | MetricType::NetUpload => "MB/s",
         }
     }
 }
@@ -125,52 +365,373 @@ pub struct GraphSettings {
     // LM Sensors settings
     pub metric_type: MetricType,
 
-    // WebSocket settings
+    // WebSocket settings, the URL and API key are also used by SSE sources
     pub websocket_url: Option<String>,
     pub websocket_api_key: Option<String>,
-    pub websocket_init_messages: Vec<String>,
+    pub websocket_init_messages: Vec<InitStep>,
+    pub websocket_mode: WebSocketMode,
+    pub websocket_subprotocols: Vec<String>,
+    pub websocket_binary_format: BinaryFormat,
+    /// Request sent periodically for servers that only reply when asked
+    pub websocket_poll_message: Option<String>,
+    pub websocket_poll_interval_secs: Option<u64>,
+    /// Ping interval, and how long without any frame before reconnecting
+    pub websocket_keepalive_secs: Option<u64>,
+    pub websocket_keepalive_timeout_secs: Option<u64>,
+
+    // Authentication settings, the API key is the secret for every mode
+    pub auth_mode: AuthMode,
+    pub auth_username: Option<String>,
+    pub auth_key_name: Option<String>,
+    /// Extra handshake headers as "Name: value" lines
+    pub extra_headers: Vec<String>,
+
+    // TLS settings for wss:// sources, paths point at PEM files
+    pub tls_ca_path: Option<String>,
+    pub tls_client_cert_path: Option<String>,
+    pub tls_client_key_path: Option<String>,
+    pub tls_server_name: Option<String>,
+    /// Skip certificate verification, only meant for lab hosts
+    pub tls_insecure: bool,
+
+    // Local socket and named pipe settings
+    pub local_path: Option<String>,
+    pub local_mode: LocalMode,
+
+    // Push settings, values are posted to /keys/<push_name>
+    pub push_name: Option<String>,
+    pub push_port: Option<u16>,
+
+    // Expression settings
+    /// Formula over metrics and named keys, e.g. `net_download + net_upload`
+    pub expression: Option<String>,
+    /// Name expressions refer to this key's value by, prefixed with the source
+    /// kind (`ws:<name>`); push keys default to their push name
+    pub source_name: Option<String>,
+
+    // Home Assistant settings
+    pub home_assistant_entity_id: Option<String>,
+
+    // Value extraction settings
+    pub value_selector: Option<String>,
+    pub message_filter: Option<String>,
+    pub text_format: TextFormat,
+    /// Pattern for the regex text format, reading the `value` group or the first one
+    pub text_pattern: Option<String>,
+    pub decimal_separator: DecimalSeparator,
+
+    // Seconds without a new value before a remote source is shown as stale
+    pub stale_timeout_secs: Option<u64>,
+
+    // History settings, long windows are downsampled to fit the graph
+    pub sample_interval_secs: Option<u64>,
+    pub window_secs: Option<u64>,
+    /// Keep sampling while the key's page isn't shown
+    pub always_record: bool,
+    /// Sample interval while hidden, defaults to the normal one
+    pub background_interval_secs: Option<u64>,
+
+    // Smoothing for the plotted line and the value text, sharing their parameters
+    pub smoothing: Smoothing,
+    pub value_smoothing: Smoothing,
+    /// Weight of new readings for the EMA, between 0 and 1
+    pub smoothing_alpha: Option<f32>,
+    /// Samples in the moving average and median windows
+    pub smoothing_samples: Option<usize>,
 
     // Display settings
     pub visualization_type: VisualizationType,
     pub show_value_text: bool,
+    /// Single level drawn in the warning color, used when `levels` is empty
     pub threshold: Option<f32>,
     pub normal_color: String,
     pub warning_color: String,
+    /// Ordered levels such as warning and critical, each with its own color
+    pub levels: Vec<LevelSettings>,
+    /// How far below a threshold values must drop to leave its level
+    pub hysteresis: Option<f32>,
+    /// Seconds a new level must hold before the colors change
+    pub dwell_secs: Option<u64>,
     pub max_value: Option<f32>,
     pub min_value: Option<f32>,
+    pub scale_mode: ScaleMode,
+    pub log_scale: bool,
+
+    // Multi-series settings, drawn by graphs only
+    /// Up to three series plotted with the key's own value
+    pub series: Vec<SeriesSettings>,
+    pub series_layout: SeriesLayout,
+    /// Legend label of the key's own value, the title when unset
+    pub label: Option<String>,
+
+    // Unit settings
+    /// Unit of values from sources that don't report one
+    pub value_unit: Option<String>,
+    /// Unit to show values in, e.g. `MiB/s` or `°F`; picked automatically when unset
+    pub display_unit: Option<String>,
+    /// Pick binary prefixes (KiB, MiB) for data
+    pub binary_units: bool,
+    /// Pick bits instead of bytes for data
+    pub bit_units: bool,
+    /// Digits after the decimal point
+    pub precision: Option<usize>,
 
     // Fan settings
     pub fan_number: Option<u32>,
 }
 
+/// Recorded values of an extra series
+struct SeriesData {
+    expression: Option<Expression>,
+    /// Identifies the series' stored history
+    metric: String,
+    history: History,
+    smoother: Smoother,
+    /// Latest value, repeated while an input is missing so the series stays
+    /// aligned with the key's own value
+    last: Option<f32>,
+    /// The stored history hasn't been looked up yet
+    unrestored: bool,
+}
+
 /// Data for a single graph instance
 pub struct GraphData {
-    data_points: VecDeque<f32>,
+    history: History,
+    /// Whether the history changed since it was last written to disk
+    history_dirty: bool,
+    /// When the key stopped being shown, it is then only kept to record in
+    /// the background
+    pub hidden_since: Option<Instant>,
+    line_smoother: Smoother,
+    value_smoother: Smoother,
+    /// Latest reading smoothed for the value text
+    display_value: Option<f32>,
+    /// Highest reading recorded, the ceiling of the `Grow` scale
+    peak: Option<f32>,
+    level_tracker: LevelTracker,
+    series: Vec<SeriesData>,
     pub settings: GraphSettings,
-    ws_client: Option<Arc<WebSocketClient>>,
+    subscription: Option<Subscription>,
+    expression: Option<Expression>,
 }
 
 impl GraphData {
     pub fn new(settings: GraphSettings) -> Self {
         Self {
-            data_points: VecDeque::with_capacity(MAX_DATA_POINTS),
+            history: new_history(&settings),
+            history_dirty: false,
+            hidden_since: None,
+            line_smoother: new_smoother(&settings, settings.smoothing),
+            value_smoother: new_smoother(&settings, settings.value_smoothing),
+            display_value: None,
+            peak: None,
+            level_tracker: LevelTracker::new(),
+            series: new_series(&settings),
             settings,
-            ws_client: None,
+            subscription: None,
+            expression: None,
         }
     }
 
     pub fn add_data_point(&mut self, value: f32) {
-        if self.data_points.len() >= MAX_DATA_POINTS {
-            self.data_points.pop_front();
+        let line_value = self.line_smoother.apply(value);
+        self.history.push(line_value);
+        self.history_dirty = true;
+        self.display_value = Some(self.value_smoother.apply(value));
+        self.peak = Some(self.peak.map_or(value, |peak| peak.max(value)));
+
+        let hysteresis = self.settings.hysteresis;
+        self.level_tracker.update(
+            line_value,
+            &self.levels(),
+            |threshold| hysteresis.unwrap_or(threshold.abs() * DEFAULT_HYSTERESIS_RATIO),
+            Duration::from_secs(self.settings.dwell_secs.unwrap_or(DEFAULT_DWELL_SECS)),
+        );
+    }
+
+    /// Threshold levels ordered by threshold, falling back to the single
+    /// threshold or the metric's default one
+    fn levels(&self) -> Vec<Level> {
+        let warning_color =
+            parse_hex_color(&self.settings.warning_color).unwrap_or(gfx::DEFAULT_WARNING_COLOR);
+
+        if self.settings.levels.is_empty() {
+            let threshold = self
+                .settings
+                .threshold
+                .or_else(|| match self.settings.data_source {
+                    DataSource::LmSensors => self.settings.metric_type.default_threshold(),
+                    DataSource::WebSocket
+                    | DataSource::Sse
+                    | DataSource::Local
+                    | DataSource::Push
+                    | DataSource::Expression => None,
+                });
+            return threshold
+                .map(|threshold| Level {
+                    threshold,
+                    color: warning_color,
+                })
+                .into_iter()
+                .collect();
+        }
+
+        let mut levels: Vec<Level> = self
+            .settings
+            .levels
+            .iter()
+            .filter(|level| level.threshold.is_finite())
+            .map(|level| Level {
+                threshold: level.threshold,
+                color: level
+                    .color
+                    .as_deref()
+                    .and_then(parse_hex_color)
+                    .unwrap_or(warning_color),
+            })
+            .collect();
+        levels.sort_by(|a, b| a.threshold.total_cmp(&b.threshold));
+        levels
+    }
+
+    /// Expressions of the extra series, read whenever a data point is added
+    pub fn series_expressions(&self) -> impl Iterator<Item = Option<&Expression>> {
+        self.series.iter().map(|series| series.expression.as_ref())
+    }
+
+    /// Record the extra series' values read along with a data point
+    pub fn add_series_points(&mut self, values: &[Option<f32>]) {
+        for (series, value) in self.series.iter_mut().zip(values) {
+            let Some(value) = value.or(series.last) else {
+                continue;
+            };
+            series.last = Some(value);
+            series.history.push(series.smoother.apply(value));
+        }
+    }
+
+    /// Latest recorded value as shown in the value text
+    pub fn display_value(&self) -> Option<f32> {
+        self.display_value
+    }
+
+    /// Format a value for display, `source_unit` being the unit an external
+    /// source reported for it
+    pub fn format_value(&self, value: f32, source_unit: Option<&str>) -> String {
+        let unit = match self.settings.data_source {
+            DataSource::LmSensors => Some(self.settings.metric_type.unit()),
+            DataSource::WebSocket
+            | DataSource::Sse
+            | DataSource::Local
+            | DataSource::Push
+            | DataSource::Expression => source_unit.or(non_empty(&self.settings.value_unit)),
+        };
+
+        self.unit_format().format(value, unit)
+    }
+
+    fn unit_format(&self) -> UnitFormat<'_> {
+        UnitFormat {
+            display_unit: non_empty(&self.settings.display_unit),
+            binary: self.settings.binary_units,
+            bits: self.settings.bit_units,
+            precision: self.settings.precision.unwrap_or(DEFAULT_PRECISION),
+        }
+    }
+
+    /// Seconds between samples while hidden, never shorter than the normal interval
+    pub fn background_interval_secs(&self) -> u64 {
+        let interval = self.sample_interval_secs();
+        let background = self.settings.background_interval_secs.unwrap_or(interval);
+        // Whole multiples keep each background reading standing for whole samples
+        background.max(interval) / interval * interval
+    }
+
+    /// Restore the stored history of the metric this key shows, if there is one
+    pub async fn load_history(&mut self, instance_id: &str) {
+        let path = match store::history_path(instance_id, &history_metric(&self.settings)) {
+            Ok(path) => path,
+            Err(e) => {
+                log::warn!("Not restoring history: {}", e);
+                return;
+            }
+        };
+
+        if let Some(history) = store::load(&path, self.sample_interval_secs(), &self.history).await
+        {
+            self.history = history;
+            self.history_dirty = false;
         }
-        self.data_points.push_back(value);
+
+        self.load_series_history(instance_id).await;
+    }
+
+    /// Restore the stored history of extra series added since the history was loaded
+    pub async fn load_series_history(&mut self, instance_id: &str) {
+        let sample_interval_secs = self.sample_interval_secs();
+
+        for series in self.series.iter_mut().filter(|series| series.unrestored) {
+            series.unrestored = false;
+            let Ok(path) = store::history_path(instance_id, &series.metric) else {
+                continue;
+            };
+            if let Some(history) = store::load(&path, sample_interval_secs, &series.history).await {
+                series.history = history;
+            }
+        }
+    }
+
+    /// Paths and contents to write if the history changed since the last snapshot,
+    /// one for the key's own value and one per extra series
+    pub fn history_snapshot(&mut self, instance_id: &str) -> Vec<(PathBuf, Vec<u8>)> {
+        if !self.history_dirty {
+            return Vec::new();
+        }
+        self.history_dirty = false;
+
+        let histories = std::iter::once((history_metric(&self.settings), &self.history)).chain(
+            self.series
+                .iter()
+                .map(|series| (series.metric.clone(), &series.history)),
+        );
+
+        histories
+            .filter_map(|(metric, history)| {
+                let snapshot = store::history_path(instance_id, &metric)
+                    .and_then(|path| Ok((path, store::snapshot(history)?)));
+                match snapshot {
+                    Ok(snapshot) => Some(snapshot),
+                    Err(e) => {
+                        log::warn!("Not saving history: {}", e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Whether new settings show a different metric, whose history is stored separately
+    pub fn metric_changed(&self, settings: &GraphSettings) -> bool {
+        history_metric(&self.settings) != history_metric(settings)
+    }
+
+    /// Whether new settings plot other extra series, which are stored separately
+    pub fn series_changed(&self, settings: &GraphSettings) -> bool {
+        !self
+            .series
+            .iter()
+            .map(|series| &series.metric)
+            .eq(series_metrics(settings).iter())
+    }
+
+    /// Seconds between recorded samples
+    pub fn sample_interval_secs(&self) -> u64 {
+        sample_interval_secs(&self.settings)
     }
 
-    pub fn get_graph_config(&self) -> GraphConfig {
-        let normal_color = parse_hex_color(&self.settings.normal_color)
-            .unwrap_or(ColorScheme::default().normal_color);
-        let warning_color = parse_hex_color(&self.settings.warning_color)
-            .unwrap_or(ColorScheme::default().warning_color);
+    pub async fn get_graph_config(&self) -> GraphConfig {
+        let normal_color =
+            parse_hex_color(&self.settings.normal_color).unwrap_or(gfx::DEFAULT_NORMAL_COLOR);
 
         // Title is always the metric name only (displayed on the graph image)
         let title = match self.settings.data_source {
@@ -187,56 +748,636 @@ impl GraphData {
                 }
             }
             DataSource::WebSocket => "WebSocket".to_string(),
+            DataSource::Sse => "SSE".to_string(),
+            DataSource::Local => match self.settings.local_mode {
+                LocalMode::Fifo => "Pipe".to_string(),
+                LocalMode::Connect | LocalMode::Listen => "Socket".to_string(),
+            },
+            DataSource::Push => non_empty(&self.settings.push_name)
+                .unwrap_or("Push")
+                .to_string(),
+            DataSource::Expression => non_empty(&self.settings.source_name)
+                .unwrap_or("Expr")
+                .to_string(),
         };
 
+        let (connection_state, stale) = self.source_status().await;
+
+        let buckets = self.history.buckets();
+        let envelope = if self.history.is_downsampled() {
+            buckets
+                .iter()
+                .map(|bucket| (bucket.min, bucket.max))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let data_points: Vec<f32> = buckets.iter().map(Bucket::mean).collect();
+        let series = self.graph_series(data_points.len());
+
+        // The shared axis covers everything drawn on it
+        let stacked = self.settings.series_layout == SeriesLayout::Stacked && !series.is_empty();
+        let extent = if stacked {
+            let layers: Vec<&[f32]> = std::iter::once(data_points.as_slice())
+                .chain(series.iter().map(|series| series.data_points.as_slice()))
+                .collect();
+            value_extent(gfx::stack(&layers).iter().flatten().map(|&v| (v, v)))
+        } else {
+            let shared = series
+                .iter()
+                .filter(|series| series.range.is_none())
+                .flat_map(|series| &series.data_points);
+            value_extent(
+                data_points
+                    .iter()
+                    .chain(shared)
+                    .map(|&value| (value, value))
+                    .chain(envelope.iter().copied()),
+            )
+        };
+        let (min_value, max_value) = self.value_range(extent);
+
+        let label = non_empty(&self.settings.label)
+            .unwrap_or(&title)
+            .to_string();
+
         GraphConfig {
-            data_points: self.data_points.iter().copied().collect(),
-            max_value: self
-                .settings
-                .max_value
-                .unwrap_or_else(|| match self.settings.data_source {
-                    DataSource::LmSensors => self.settings.metric_type.default_max(),
-                    DataSource::WebSocket => 100.0,
-                }),
-            min_value: self.settings.min_value.unwrap_or(0.0),
-            threshold: self
-                .settings
-                .threshold
-                .or_else(|| match self.settings.data_source {
-                    DataSource::LmSensors => self.settings.metric_type.default_threshold(),
-                    DataSource::WebSocket => None,
-                }),
+            data_points,
+            envelope,
+            max_value,
+            min_value,
+            log_scale: self.settings.log_scale,
             color_scheme: ColorScheme {
                 normal_color,
-                warning_color,
+                levels: self.levels(),
             },
+            level: self.level_tracker.level(),
             title,
+            connection_state,
+            stale,
+            series,
+            stacked,
+            label,
         }
     }
 
-    pub async fn initialize_websocket(&mut self) -> Result<()> {
-        if self.settings.data_source == DataSource::WebSocket {
-            if let Some(url) = &self.settings.websocket_url {
+    /// Extra series to draw, cut to the `slots` of the key's own data points
+    fn graph_series(&self, slots: usize) -> Vec<Series> {
+        let stacked = self.settings.series_layout == SeriesLayout::Stacked;
+
+        self.series
+            .iter()
+            .zip(&self.settings.series)
+            .zip(SERIES_COLORS)
+            .map(|((data, settings), default_color)| {
+                let buckets = data.history.buckets();
+                let data_points: Vec<f32> = buckets[buckets.len().saturating_sub(slots)..]
+                    .iter()
+                    .map(Bucket::mean)
+                    .collect();
+
+                // An independent axis always fits its series, a fixed range
+                // would be the shared axis' one
+                let range = (settings.own_axis && !stacked).then(|| {
+                    let mode = match self.settings.scale_mode {
+                        ScaleMode::Fixed => ScaleMode::Nice,
+                        mode => mode,
+                    };
+                    let extent = value_extent(data_points.iter().map(|&value| (value, value)));
+                    auto_range(mode, extent, None, self.default_max())
+                });
+
+                Series {
+                    color: settings
+                        .color
+                        .as_deref()
+                        .and_then(parse_hex_color)
+                        .or_else(|| parse_hex_color(default_color))
+                        .unwrap_or(gfx::DEFAULT_NORMAL_COLOR),
+                    label: non_empty(&settings.label)
+                        .unwrap_or(settings.expression.trim())
+                        .to_string(),
+                    data_points,
+                    range,
+                }
+            })
+            .collect()
+    }
+
+    /// Top of the value axis when the scale doesn't depend on the data
+    fn default_max(&self) -> f32 {
+        match self.settings.data_source {
+            DataSource::LmSensors => self.settings.metric_type.default_max(),
+            DataSource::WebSocket
+            | DataSource::Sse
+            | DataSource::Local
+            | DataSource::Push
+            | DataSource::Expression => 100.0,
+        }
+    }
+
+    /// Bounds of the shared value axis given the lowest and highest values drawn
+    fn value_range(&self, extent: Option<(f32, f32)>) -> (f32, f32) {
+        let (min, max) = auto_range(
+            self.settings.scale_mode,
+            extent,
+            self.peak,
+            self.default_max(),
+        );
+
+        (
+            self.settings.min_value.unwrap_or(min),
+            self.settings.max_value.unwrap_or(max),
+        )
+    }
+
+    /// Apply new settings, re-subscribing to the remote source if any of its
+    /// settings changed
+    pub async fn update_settings(&mut self, settings: GraphSettings) -> Result<()> {
+        let resubscribe = source_settings(&self.settings) != source_settings(&settings);
+        let reset_history = self.metric_changed(&settings);
+        let reset_smoothing =
+            reset_history || smoothing_settings(&self.settings) != smoothing_settings(&settings);
+        let series_changed = self.series_changed(&settings);
+        let reset_level = reset_history
+            || self.settings.levels != settings.levels
+            || self.settings.threshold != settings.threshold;
+        self.settings = settings;
+
+        if reset_history {
+            self.history = new_history(&self.settings);
+            self.history_dirty = false;
+            self.peak = None;
+        }
+        if reset_smoothing {
+            self.line_smoother = new_smoother(&self.settings, self.settings.smoothing);
+            self.value_smoother = new_smoother(&self.settings, self.settings.value_smoothing);
+            self.display_value = None;
+            for series in &mut self.series {
+                series.smoother = new_smoother(&self.settings, self.settings.smoothing);
+            }
+        }
+        if reset_history {
+            self.series = new_series(&self.settings);
+        } else if series_changed {
+            // Series that are still plotted keep their history
+            let mut previous = std::mem::take(&mut self.series);
+            self.series = new_series(&self.settings)
+                .into_iter()
+                .map(|series| {
+                    match previous
+                        .iter()
+                        .position(|kept| kept.metric == series.metric)
+                    {
+                        Some(index) => previous.swap_remove(index),
+                        None => series,
+                    }
+                })
+                .collect();
+        }
+        if reset_level {
+            self.level_tracker = LevelTracker::new();
+        }
+
+        if resubscribe {
+            self.initialize_source().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Subscribe to the configured remote source, replacing any previous
+    /// subscription. A connection that is no longer used is closed.
+    pub async fn initialize_source(&mut self) -> Result<()> {
+        // Expressions don't subscribe, they read their inputs when sampled
+        let expression = match (
+            self.settings.data_source,
+            non_empty(&self.settings.expression),
+        ) {
+            (DataSource::Expression, Some(expression)) => Expression::parse(expression)
+                .context("Invalid expression")
+                .map(Some),
+            _ => Ok(None),
+        };
+        let source = match expression {
+            Ok(expression) => {
+                self.expression = expression;
+                self.subscribe_source().await
+            }
+            Err(e) => {
+                self.expression = None;
+                Err(e)
+            }
+        };
+
+        match source {
+            Ok(subscription) => {
+                self.subscription = subscription;
+                Ok(())
+            }
+            Err(e) => {
+                self.subscription = None;
+                Err(e)
+            }
+        }
+    }
+
+    async fn subscribe_source(&self) -> Result<Option<Subscription>> {
+        let url = non_empty(&self.settings.websocket_url);
+
+        match self.settings.data_source {
+            DataSource::LmSensors | DataSource::Expression => Ok(None),
+            DataSource::WebSocket => {
+                let Some(url) = url else {
+                    return Ok(None);
+                };
                 let config = WebSocketConfig {
-                    url: url.clone(),
-                    api_key: self.settings.websocket_api_key.clone(),
-                    init_messages: self.settings.websocket_init_messages.clone(),
+                    url: url.to_string(),
+                    auth: self.auth_config()?,
+                    subprotocols: self
+                        .settings
+                        .websocket_subprotocols
+                        .iter()
+                        .map(|p| p.trim().to_string())
+                        .filter(|p| !p.is_empty())
+                        .collect(),
+                    init_steps: self.settings.websocket_init_messages.clone(),
+                    binary_format: self.settings.websocket_binary_format,
+                    poll_message: non_empty(&self.settings.websocket_poll_message)
+                        .map(str::to_string),
+                    poll_interval: positive_secs(self.settings.websocket_poll_interval_secs),
+                    keepalive_interval: positive_secs(self.settings.websocket_keepalive_secs),
+                    keepalive_timeout: positive_secs(
+                        self.settings.websocket_keepalive_timeout_secs,
+                    ),
+                    mode: self.settings.websocket_mode,
+                    tls: self.tls_config(),
                 };
 
-                let client = Arc::new(WebSocketClient::new(config));
-                client.start().await?;
-                self.ws_client = Some(client);
+                let subscription = self.subscription_config()?;
+                if config.mode == WebSocketMode::HomeAssistant && subscription.entity_id.is_none() {
+                    return Err(anyhow!("Home Assistant mode requires an entity ID"));
+                }
+
+                Ok(Some(
+                    WebSocketClient::subscribe(config, subscription).await?,
+                ))
             }
+            DataSource::Sse => {
+                let Some(url) = url else {
+                    return Ok(None);
+                };
+                let config = SseConfig {
+                    url: url.to_string(),
+                    auth: self.auth_config()?,
+                    tls: self.tls_config(),
+                };
+
+                Ok(Some(
+                    SseClient::subscribe(config, self.subscription_config()?).await,
+                ))
+            }
+            DataSource::Local => {
+                let Some(path) = non_empty(&self.settings.local_path) else {
+                    return Ok(None);
+                };
+                let config = LocalConfig {
+                    path: PathBuf::from(expand_home(path)),
+                    mode: self.settings.local_mode,
+                };
+
+                Ok(Some(
+                    LocalClient::subscribe(config, self.subscription_config()?).await,
+                ))
+            }
+            DataSource::Push => {
+                let Some(name) = non_empty(&self.settings.push_name) else {
+                    return Ok(None);
+                };
+                let subscription = SubscriptionConfig {
+                    push_name: Some(name.to_string()),
+                    ..self.subscription_config()?
+                };
+                let port = self.settings.push_port.unwrap_or(DEFAULT_PUSH_PORT);
+
+                Ok(Some(PushServer::subscribe(port, subscription).await))
+            }
+        }
+    }
+
+    fn auth_config(&self) -> Result<AuthConfig> {
+        Ok(AuthConfig {
+            api_key: non_empty(&self.settings.websocket_api_key).map(str::to_string),
+            mode: self.settings.auth_mode,
+            username: non_empty(&self.settings.auth_username).map(str::to_string),
+            key_name: non_empty(&self.settings.auth_key_name).map(str::to_string),
+            headers: parse_headers(&self.settings.extra_headers)?,
+        })
+    }
+
+    fn tls_config(&self) -> TlsConfig {
+        TlsConfig {
+            ca_path: non_empty(&self.settings.tls_ca_path).map(str::to_string),
+            client_cert_path: non_empty(&self.settings.tls_client_cert_path).map(str::to_string),
+            client_key_path: non_empty(&self.settings.tls_client_key_path).map(str::to_string),
+            server_name: non_empty(&self.settings.tls_server_name).map(str::to_string),
+            insecure: self.settings.tls_insecure,
         }
+    }
 
-        Ok(())
+    /// What this key reads from its source's messages
+    fn subscription_config(&self) -> Result<SubscriptionConfig> {
+        Ok(SubscriptionConfig {
+            entity_id: non_empty(&self.settings.home_assistant_entity_id).map(str::to_string),
+            push_name: None,
+            selector: non_empty(&self.settings.value_selector)
+                .map(ValueSelector::parse)
+                .transpose()?,
+            filter: non_empty(&self.settings.message_filter)
+                .map(MessageFilter::parse)
+                .transpose()?,
+            text: TextExtractor::new(
+                self.settings.text_format,
+                non_empty(&self.settings.text_pattern),
+                self.settings.decimal_separator,
+            )?,
+        })
+    }
+
+    pub fn get_subscription(&self) -> Option<&Subscription> {
+        self.subscription.as_ref()
     }
 
-    pub fn get_ws_client(&self) -> Option<&Arc<WebSocketClient>> {
-        self.ws_client.as_ref()
+    pub fn get_expression(&self) -> Option<&Expression> {
+        self.expression.as_ref()
+    }
+
+    /// Name expressions refer to this key's value by, e.g. `ws:outside_temp`
+    pub fn reference_name(&self) -> Option<String> {
+        let kind = match self.settings.data_source {
+            DataSource::LmSensors => return None,
+            DataSource::WebSocket => "ws",
+            DataSource::Sse => "sse",
+            DataSource::Local => "local",
+            DataSource::Push => "push",
+            DataSource::Expression => "expr",
+        };
+
+        let name = match self.settings.data_source {
+            DataSource::Push => {
+                non_empty(&self.settings.source_name).or(non_empty(&self.settings.push_name))
+            }
+            _ => non_empty(&self.settings.source_name),
+        };
+        name.map(|name| format!("{}:{}", kind, name))
+    }
+
+    /// Connection state of the remote source (if any) and whether its value is stale
+    pub async fn source_status(&self) -> (Option<ConnectionState>, bool) {
+        let Some(subscription) = &self.subscription else {
+            return (None, false);
+        };
+
+        let stale = match self.settings.stale_timeout_secs {
+            Some(secs) => subscription.is_stale(Duration::from_secs(secs)).await,
+            None => false,
+        };
+
+        (Some(subscription.connection_state().await), stale)
+    }
+}
+
+fn sample_interval_secs(settings: &GraphSettings) -> u64 {
+    settings
+        .sample_interval_secs
+        .unwrap_or(DEFAULT_SAMPLE_INTERVAL_SECS)
+        .max(1)
+}
+
+/// Empty history sized for the configured window
+fn new_history(settings: &GraphSettings) -> History {
+    let window_secs = settings.window_secs.unwrap_or(DEFAULT_WINDOW_SECS);
+    let window_samples = window_secs.div_ceil(sample_interval_secs(settings));
+    History::new(window_samples as usize, PLOT_WIDTH as usize)
+}
+
+fn new_series(settings: &GraphSettings) -> Vec<SeriesData> {
+    settings
+        .series
+        .iter()
+        .take(MAX_EXTRA_SERIES)
+        .zip(series_metrics(settings))
+        .map(|(series, metric)| {
+            let expression = match Expression::parse(&series.expression) {
+                Ok(expression) => Some(expression),
+                Err(e) => {
+                    log::warn!("Invalid series expression '{}': {}", series.expression, e);
+                    None
+                }
+            };
+
+            SeriesData {
+                expression,
+                metric,
+                history: new_history(settings),
+                smoother: new_smoother(settings, settings.smoothing),
+                last: None,
+                unrestored: true,
+            }
+        })
+        .collect()
+}
+
+/// Identifies what each extra series' history shows
+fn series_metrics(settings: &GraphSettings) -> Vec<String> {
+    let metric = history_metric(settings);
+    settings
+        .series
+        .iter()
+        .take(MAX_EXTRA_SERIES)
+        .map(|series| format!("{} {}", metric, series.expression.trim()))
+        .collect()
+}
+
+fn new_smoother(settings: &GraphSettings, mode: Smoothing) -> Smoother {
+    Smoother::new(
+        mode,
+        settings.smoothing_alpha.unwrap_or(DEFAULT_SMOOTHING_ALPHA),
+        settings
+            .smoothing_samples
+            .unwrap_or(DEFAULT_SMOOTHING_SAMPLES),
+    )
+}
+
+fn smoothing_settings(settings: &GraphSettings) -> impl PartialEq {
+    (
+        settings.smoothing,
+        settings.value_smoothing,
+        settings.smoothing_alpha,
+        settings.smoothing_samples,
+    )
+}
+
+/// Identifies what a key's history shows, including how it is sampled
+fn history_metric(settings: &GraphSettings) -> String {
+    format!(
+        "{:?}",
+        (
+            settings.data_source,
+            settings.metric_type,
+            settings.fan_number,
+            &settings.websocket_url,
+            &settings.home_assistant_entity_id,
+            &settings.value_selector,
+            &settings.local_path,
+            &settings.push_name,
+            &settings.expression,
+            sample_interval_secs(settings),
+            settings.window_secs.unwrap_or(DEFAULT_WINDOW_SECS),
+        )
+    )
+}
+
+/// Settings that identify what a key subscribes to; changing any of them
+/// requires a new subscription
+fn source_settings(settings: &GraphSettings) -> impl PartialEq + '_ {
+    (
+        settings.data_source,
+        (
+            &settings.websocket_url,
+            &settings.websocket_init_messages,
+            settings.websocket_mode,
+            &settings.websocket_subprotocols,
+            &settings.extra_headers,
+            settings.websocket_binary_format,
+        ),
+        (
+            &settings.websocket_poll_message,
+            settings.websocket_poll_interval_secs,
+            settings.websocket_keepalive_secs,
+            settings.websocket_keepalive_timeout_secs,
+        ),
+        (
+            &settings.websocket_api_key,
+            settings.auth_mode,
+            &settings.auth_username,
+            &settings.auth_key_name,
+        ),
+        (&settings.local_path, settings.local_mode),
+        (&settings.push_name, settings.push_port),
+        &settings.expression,
+        (
+            &settings.tls_ca_path,
+            &settings.tls_client_cert_path,
+            &settings.tls_client_key_path,
+            &settings.tls_server_name,
+            settings.tls_insecure,
+        ),
+        (
+            &settings.home_assistant_entity_id,
+            &settings.value_selector,
+            &settings.message_filter,
+            settings.text_format,
+            &settings.text_pattern,
+            settings.decimal_separator,
+        ),
+    )
+}
+
+/// Parse "Name: value" header lines, skipping blank ones
+fn parse_headers(lines: &[String]) -> Result<Vec<(String, String)>> {
+    lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Invalid header '{}': expected 'Name: value'", line))?;
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Treat blank optional text settings the same as unset ones
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// A duration in seconds, unset when zero since timers can't tick that fast
+fn positive_secs(secs: Option<u64>) -> Option<Duration> {
+    secs.filter(|&secs| secs > 0).map(Duration::from_secs)
+}
+
+/// Lowest and highest of some (low, high) pairs
+fn value_extent(values: impl Iterator<Item = (f32, f32)>) -> Option<(f32, f32)> {
+    values.reduce(|(low, high), (min, max)| (low.min(min), high.max(max)))
+}
+
+/// Axis bounds for values spanning `extent`, the metric's default range when
+/// there are none yet. `peak` is the highest value seen, for the `Grow` mode.
+fn auto_range(
+    mode: ScaleMode,
+    extent: Option<(f32, f32)>,
+    peak: Option<f32>,
+    default_max: f32,
+) -> (f32, f32) {
+    match (mode, extent) {
+        (ScaleMode::Fixed, _) | (_, None) => (0.0, default_max),
+        (ScaleMode::Fit, Some((low, high))) => (low, high),
+        (ScaleMode::Nice, Some((low, high))) => nice_range(low, high),
+        (ScaleMode::Grow, Some((low, high))) => {
+            let ceiling = peak.map_or(high, |peak| peak.max(high));
+            (low.min(0.0), nice_ceil(ceiling).unwrap_or(default_max))
+        }
     }
 }
 
+/// Pad a range by a tenth of its span and round it out to multiples of a
+/// 1, 2 or 5 step. Ranges that don't cross zero stay on their side of it,
+/// with zero itself counting as the positive side so idle values sit at the
+/// bottom.
+fn nice_range(low: f32, high: f32) -> (f32, f32) {
+    let headroom = match high - low {
+        span if span > 0.0 => span * 0.1,
+        _ => high.abs().max(1.0) * 0.1,
+    };
+    let padded_low = if low >= 0.0 {
+        (low - headroom).max(0.0)
+    } else {
+        low - headroom
+    };
+    let padded_high = if high < 0.0 {
+        (high + headroom).min(0.0)
+    } else {
+        high + headroom
+    };
+
+    let step = nice_step((padded_high - padded_low) / 4.0);
+    (
+        (padded_low / step).floor() * step,
+        (padded_high / step).ceil() * step,
+    )
+}
+
+/// Smallest 1, 2 or 5 times a power of ten that is at least `value`
+fn nice_ceil(value: f32) -> Option<f32> {
+    if value <= 0.0 || !value.is_finite() {
+        return None;
+    }
+
+    let magnitude = 10f32.powf(value.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|factor| factor * magnitude)
+        .find(|&nice| nice >= value)
+}
+
+/// Round a step up to 1, 2 or 5 times a power of ten
+fn nice_step(step: f32) -> f32 {
+    nice_ceil(step).unwrap_or(1.0)
+}
+
 /// Parse hex color string to RGBA
 fn parse_hex_color(hex: &str) -> Option<image::Rgba<u8>> {
     let hex = hex.trim_start_matches('#');
@@ -250,3 +1391,91 @@ fn parse_hex_color(hex: &str) -> Option<image::Rgba<u8>> {
 
     Some(image::Rgba([r, g, b, 255]))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nice_range_keeps_idle_values_at_the_bottom() {
+        let (low, high) = nice_range(0.0, 0.0);
+        assert_eq!(low, 0.0);
+        assert!(high > 0.0);
+    }
+
+    #[test]
+    fn nice_range_pads_and_rounds_out() {
+        assert_eq!(nice_range(0.0, 100.0), (0.0, 150.0));
+        assert_eq!(nice_range(12.0, 47.0), (0.0, 60.0));
+        assert_eq!(nice_range(-47.0, -12.0), (-60.0, 0.0));
+        assert_eq!(nice_range(-10.0, 10.0), (-20.0, 20.0));
+        assert_eq!(nice_range(101.0, 104.0), (100.0, 105.0));
+    }
+
+    #[test]
+    fn nice_range_stays_on_its_side_of_zero() {
+        let (low, _) = nice_range(1.0, 50.0);
+        assert_eq!(low, 0.0);
+        let (_, high) = nice_range(-50.0, -1.0);
+        assert_eq!(high, 0.0);
+    }
+
+    #[test]
+    fn nice_range_handles_flat_values() {
+        let (low, high) = nice_range(42.0, 42.0);
+        assert!(low < 42.0 && high > 42.0);
+        let (low, high) = nice_range(-3.0, -3.0);
+        assert!(low < -3.0 && high > -3.0 && high <= 0.0);
+    }
+
+    #[test]
+    fn legacy_init_messages_wait_for_a_reply() {
+        let steps: Vec<InitStep> =
+            serde_json::from_str(r#"["login", {"message": "subscribe"}]"#).unwrap();
+        assert!(steps[0].wait_for_reply);
+        assert_eq!(steps[0].message, "login");
+        assert!(!steps[1].wait_for_reply);
+    }
+
+    #[tokio::test]
+    async fn editing_series_keeps_the_history_of_unchanged_ones() {
+        let series = |expression: &str, label: &str| SeriesSettings {
+            expression: expression.to_string(),
+            label: Some(label.to_string()),
+            ..Default::default()
+        };
+        let mut settings = GraphSettings {
+            series: vec![series("cpu_load", "CPU"), series("gpu_load", "GPU")],
+            ..Default::default()
+        };
+        let mut graph_data = GraphData::new(settings.clone());
+        for _ in 0..5 {
+            graph_data.add_series_points(&[Some(1.0), Some(2.0)]);
+        }
+
+        settings.series = vec![series("gpu_load", "Graphics"), series("ram_usage", "RAM")];
+        graph_data.update_settings(settings).await.unwrap();
+
+        assert_eq!(graph_data.series[0].history.buckets().len(), 5);
+        assert!(graph_data.series[1].history.buckets().is_empty());
+        assert!(graph_data.series[1].unrestored);
+    }
+
+    #[test]
+    fn nice_ceil_rounds_up_to_1_2_5() {
+        assert_eq!(nice_ceil(1.0), Some(1.0));
+        assert_eq!(nice_ceil(1.5), Some(2.0));
+        assert_eq!(nice_ceil(3.0), Some(5.0));
+        assert_eq!(nice_ceil(7.0), Some(10.0));
+        assert_eq!(nice_ceil(1000.0), Some(1000.0));
+        assert_eq!(nice_ceil(0.3), Some(0.5));
+    }
+
+    #[test]
+    fn nice_ceil_rejects_non_positive_values() {
+        assert_eq!(nice_ceil(0.0), None);
+        assert_eq!(nice_ceil(-5.0), None);
+        assert_eq!(nice_ceil(f32::NAN), None);
+        assert_eq!(nice_ceil(f32::INFINITY), None);
+    }
+}
@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Recorded values for one key, downsampled to fit the plot
+///
+/// A window longer than the plot is wide aggregates consecutive samples into
+/// one bucket, so e.g. 24 hours of one-second samples still draw one point
+/// per pixel column. Buckets keep the min and max so short spikes survive.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct History {
+    buckets: VecDeque<Bucket>,
+    pending: Option<Bucket>,
+    capacity: usize,
+    samples_per_bucket: usize,
+}
+
+/// Samples aggregated into one plotted point
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bucket {
+    pub min: f32,
+    pub max: f32,
+    sum: f32,
+    count: usize,
+}
+
+impl Bucket {
+    fn new(value: f32) -> Self {
+        Self {
+            min: value,
+            max: value,
+            sum: value,
+            count: 1,
+        }
+    }
+
+    fn add(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.sum / self.count as f32
+    }
+}
+
+impl History {
+    /// History holding `window_samples` samples in at most `max_points` buckets
+    pub fn new(window_samples: usize, max_points: usize) -> Self {
+        let window_samples = window_samples.max(1);
+        let samples_per_bucket = window_samples.div_ceil(max_points.max(1));
+
+        Self {
+            buckets: VecDeque::new(),
+            pending: None,
+            capacity: window_samples.div_ceil(samples_per_bucket),
+            samples_per_bucket,
+        }
+    }
+
+    pub fn push(&mut self, value: f32) {
+        let bucket = match &mut self.pending {
+            Some(bucket) => {
+                bucket.add(value);
+                *bucket
+            }
+            None => *self.pending.insert(Bucket::new(value)),
+        };
+
+        if bucket.count >= self.samples_per_bucket {
+            self.buckets.push_back(bucket);
+            self.pending = None;
+
+            while self.buckets.len() > self.capacity {
+                self.buckets.pop_front();
+            }
+        }
+    }
+
+    /// Account for samples that were never recorded, e.g. while the plugin
+    /// wasn't running, so buckets that are now older than the window drop out
+    pub fn skip(&mut self, samples: usize) {
+        let skipped = samples / self.samples_per_bucket;
+        if skipped == 0 {
+            return;
+        }
+
+        self.buckets.extend(self.pending.take());
+        let keep = self.capacity.saturating_sub(skipped);
+        while self.buckets.len() > keep {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Whether this history was recorded with the same bucket size and capacity,
+    /// e.g. to check one read from disk against the configured window
+    pub fn has_layout_of(&self, other: &History) -> bool {
+        self.samples_per_bucket == other.samples_per_bucket && self.capacity == other.capacity
+    }
+
+    /// Whether buckets hold more than one sample each
+    pub fn is_downsampled(&self) -> bool {
+        self.samples_per_bucket > 1
+    }
+
+    /// Buckets to plot, oldest first. A partly filled bucket is included so
+    /// the graph follows the latest value.
+    pub fn buckets(&self) -> Vec<Bucket> {
+        let skip = (self.buckets.len() + self.pending.iter().len()).saturating_sub(self.capacity);
+
+        self.buckets
+            .iter()
+            .copied()
+            .skip(skip)
+            .chain(self.pending)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::PLOT_WIDTH;
+
+    #[test]
+    fn a_day_of_samples_fits_the_plot() {
+        let mut history = History::new(24 * 60 * 60, PLOT_WIDTH as usize);
+        assert!(history.is_downsampled());
+
+        for i in 0..24 * 60 * 60 {
+            history.push(i as f32);
+        }
+        assert!(history.buckets().len() <= PLOT_WIDTH as usize);
+
+        // A short window keeps every sample
+        let history = History::new(60, PLOT_WIDTH as usize);
+        assert!(!history.is_downsampled());
+    }
+
+    #[test]
+    fn old_samples_are_evicted() {
+        let mut history = History::new(4, 4);
+        for i in 0..10 {
+            history.push(i as f32);
+        }
+
+        let means: Vec<f32> = history.buckets().iter().map(Bucket::mean).collect();
+        assert_eq!(means, [6.0, 7.0, 8.0, 9.0]);
+
+        // With downsampling, the partly filled bucket counts towards capacity
+        let mut history = History::new(6, 3);
+        for i in 0..7 {
+            history.push(i as f32);
+        }
+        let buckets = history.buckets();
+        assert_eq!(buckets.len(), 3);
+        assert_eq!(buckets[0].mean(), 2.5);
+        assert_eq!(buckets[2].mean(), 6.0);
+    }
+
+    #[test]
+    fn buckets_keep_spikes_and_the_mean() {
+        let mut history = History::new(40, 10);
+        let samples = [10.0, 10.0, 95.0, 10.0, 20.0, 20.0, -5.0, 20.0];
+        for value in samples {
+            history.push(value);
+        }
+
+        let buckets = history.buckets();
+        assert_eq!(buckets.len(), 2);
+        assert_eq!((buckets[0].min, buckets[0].max), (10.0, 95.0));
+        assert_eq!(buckets[0].mean(), 31.25);
+        assert_eq!((buckets[1].min, buckets[1].max), (-5.0, 20.0));
+        assert_eq!(buckets[1].mean(), 13.75);
+
+        // A partly filled bucket reports what it has so far
+        history.push(50.0);
+        let pending = history.buckets()[2];
+        assert_eq!(
+            (pending.min, pending.max, pending.mean()),
+            (50.0, 50.0, 50.0)
+        );
+    }
+}
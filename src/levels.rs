@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant};
+
+use crate::gfx::Level;
+
+/// Follows which threshold level a stream of values is in
+///
+/// A value enters a level once it is above the level's threshold, but only
+/// leaves it after dropping `hysteresis` below, so values hovering at a
+/// threshold don't flip the color back and forth. Any change has to hold for
+/// `dwell` before it takes effect.
+pub struct LevelTracker {
+    current: usize,
+    /// Level the values moved to and since when
+    pending: Option<(usize, Instant)>,
+}
+
+impl LevelTracker {
+    pub fn new() -> Self {
+        Self {
+            current: 0,
+            pending: None,
+        }
+    }
+
+    /// Active level, 0 below every threshold and `n` in `levels[n - 1]`
+    pub fn level(&self) -> usize {
+        self.current
+    }
+
+    /// Feed a value, `levels` ordered by threshold and `hysteresis` giving
+    /// the margin below a threshold
+    pub fn update(
+        &mut self,
+        value: f32,
+        levels: &[Level],
+        hysteresis: impl Fn(f32) -> f32,
+        dwell: Duration,
+    ) -> usize {
+        let target = levels
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|&(index, level)| {
+                let threshold = if index < self.current {
+                    level.threshold - hysteresis(level.threshold)
+                } else {
+                    level.threshold
+                };
+                value > threshold
+            })
+            .map_or(0, |(index, _)| index + 1);
+
+        if target == self.current {
+            self.pending = None;
+            return self.current;
+        }
+
+        let since = match self.pending {
+            Some((level, since)) if level == target => since,
+            _ => self.pending.insert((target, Instant::now())).1,
+        };
+        if since.elapsed() >= dwell {
+            self.current = target;
+            self.pending = None;
+        }
+
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const LEVELS: [Level; 3] = [
+        Level {
+            threshold: 50.0,
+            color: Rgba([255, 255, 0, 255]),
+        },
+        Level {
+            threshold: 70.0,
+            color: Rgba([255, 128, 0, 255]),
+        },
+        Level {
+            threshold: 90.0,
+            color: Rgba([255, 0, 0, 255]),
+        },
+    ];
+
+    fn margin(_threshold: f32) -> f32 {
+        5.0
+    }
+
+    fn feed(tracker: &mut LevelTracker, values: &[f32]) -> usize {
+        for &value in values {
+            tracker.update(value, &LEVELS, margin, Duration::ZERO);
+        }
+        tracker.level()
+    }
+
+    #[test]
+    fn hovering_at_a_threshold_keeps_the_level() {
+        let mut tracker = LevelTracker::new();
+        assert_eq!(feed(&mut tracker, &[49.0, 50.0, 49.9]), 0);
+
+        assert_eq!(feed(&mut tracker, &[50.1]), 1);
+        assert_eq!(feed(&mut tracker, &[49.9, 50.1, 49.0, 50.2, 48.0]), 1);
+    }
+
+    #[test]
+    fn leaving_a_level_takes_the_hysteresis_margin() {
+        let mut tracker = LevelTracker::new();
+        assert_eq!(feed(&mut tracker, &[75.0]), 2);
+
+        assert_eq!(feed(&mut tracker, &[65.1]), 2);
+        assert_eq!(feed(&mut tracker, &[65.0]), 1);
+
+        // Going back up needs the threshold itself, not the margin below it
+        assert_eq!(feed(&mut tracker, &[68.0]), 1);
+        assert_eq!(feed(&mut tracker, &[70.5]), 2);
+    }
+
+    #[test]
+    fn changes_wait_for_the_dwell_time() {
+        let dwell = Duration::from_millis(50);
+        let mut tracker = LevelTracker::new();
+
+        assert_eq!(tracker.update(60.0, &LEVELS, margin, dwell), 0);
+        assert_eq!(tracker.update(60.0, &LEVELS, margin, dwell), 0);
+        std::thread::sleep(dwell);
+        assert_eq!(tracker.update(60.0, &LEVELS, margin, dwell), 1);
+
+        // A blip back down restarts the wait
+        assert_eq!(tracker.update(95.0, &LEVELS, margin, dwell), 1);
+        std::thread::sleep(dwell);
+        assert_eq!(tracker.update(60.0, &LEVELS, margin, dwell), 1);
+        assert_eq!(tracker.update(95.0, &LEVELS, margin, dwell), 1);
+        std::thread::sleep(dwell);
+        assert_eq!(tracker.update(95.0, &LEVELS, margin, dwell), 3);
+    }
+
+    #[test]
+    fn jumps_land_on_the_right_level() {
+        let mut tracker = LevelTracker::new();
+        assert_eq!(feed(&mut tracker, &[95.0]), 3);
+        assert_eq!(feed(&mut tracker, &[10.0]), 0);
+        assert_eq!(feed(&mut tracker, &[80.0]), 2);
+
+        // Within the margin of the old level but well below the ones in between
+        assert_eq!(feed(&mut tracker, &[95.0, 47.0]), 1);
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::net::unix::pipe;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinSet;
+
+use crate::graph_data::LocalMode;
+use crate::source::{
+    self, Backoff, ConnectionPool, ConnectionState, SharedState, Subscribers, Subscription,
+    SubscriptionConfig,
+};
+
+lazy_static! {
+    static ref CONNECTIONS: ConnectionPool<LocalConfig> = ConnectionPool::new();
+}
+
+/// Local socket or named pipe configuration
+///
+/// Keys reading the same path share a single socket or pipe.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct LocalConfig {
+    pub path: PathBuf,
+    pub mode: LocalMode,
+}
+
+/// Data source reading newline-delimited JSON or text from a Unix socket or FIFO
+///
+/// Every line is handled like a WebSocket text message.
+pub struct LocalClient;
+
+impl LocalClient {
+    /// Subscribe to a socket or pipe, reusing an open one for the same path if possible
+    pub async fn subscribe(config: LocalConfig, subscription: SubscriptionConfig) -> Subscription {
+        let name = config.path.display().to_string();
+        CONNECTIONS
+            .subscribe(config.clone(), &name, subscription, |subscribers, state| {
+                Self::run_connection(config, subscribers, state)
+            })
+            .await
+    }
+
+    async fn run_connection(config: LocalConfig, subscribers: Subscribers, state: SharedState) {
+        let mut backoff = Backoff::default();
+
+        loop {
+            let result = match config.mode {
+                LocalMode::Connect => Self::run_client(&config.path, &subscribers, &state).await,
+                LocalMode::Listen => Self::run_listener(&config.path, &subscribers, &state).await,
+                LocalMode::Fifo => Self::run_fifo(&config.path, &subscribers, &state).await,
+            };
+
+            let delay = backoff.finish("Local source", &state, result).await;
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Connect to a daemon's socket and read until it hangs up
+    async fn run_client(path: &Path, subscribers: &Subscribers, state: &SharedState) -> Result<()> {
+        log::info!("Connecting to Unix socket: {}", path.display());
+
+        let stream = UnixStream::connect(path)
+            .await
+            .with_context(|| format!("Failed to connect to {}", path.display()))?;
+        *state.lock().await = ConnectionState::Connected;
+
+        read_lines(stream, subscribers).await?;
+        log::info!("Unix socket closed by peer");
+        Ok(())
+    }
+
+    /// Listen on a socket and read from every client that connects
+    async fn run_listener(
+        path: &Path,
+        subscribers: &Subscribers,
+        state: &SharedState,
+    ) -> Result<()> {
+        remove_stale_socket(path).await?;
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to listen on {}", path.display()))?;
+        let _socket_file = SocketFile(path);
+
+        log::info!("Listening on Unix socket: {}", path.display());
+        *state.lock().await = ConnectionState::Connected;
+
+        // Clients are aborted along with the listener when the last key goes away
+        let mut clients = JoinSet::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, _) = accepted?;
+                    log::debug!("Client connected to {}", path.display());
+
+                    let subscribers = subscribers.clone();
+                    clients.spawn(async move {
+                        if let Err(e) = read_lines(stream, &subscribers).await {
+                            log::debug!("Unix socket client error: {}", e);
+                        }
+                    });
+                }
+                Some(_) = clients.join_next() => {}
+            }
+        }
+    }
+
+    /// Read a named pipe, creating it if it doesn't exist yet
+    async fn run_fifo(path: &Path, subscribers: &Subscribers, state: &SharedState) -> Result<()> {
+        if !path.exists() {
+            create_fifo(path)?;
+        }
+
+        // Holding the write end open as well means the pipe doesn't hit EOF
+        // every time a writer finishes, so scripts can come and go
+        let receiver = pipe::OpenOptions::new()
+            .read_write(true)
+            .open_receiver(path)
+            .with_context(|| format!("Failed to open named pipe {}", path.display()))?;
+
+        log::info!("Reading named pipe: {}", path.display());
+        *state.lock().await = ConnectionState::Connected;
+
+        read_lines(receiver, subscribers).await?;
+        Ok(())
+    }
+}
+
+/// Hand every non-empty line to the subscribers
+async fn read_lines<R: AsyncRead + Unpin>(reader: R, subscribers: &Subscribers) -> Result<()> {
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        log::debug!("Received line: {}", line);
+        source::dispatch_text(subscribers, line).await;
+    }
+
+    Ok(())
+}
+
+/// Remove a socket file left behind by a previous run, refusing to touch
+/// other files or a socket someone is still listening on
+async fn remove_stale_socket(path: &Path) -> Result<()> {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(anyhow!("{} exists and is not a socket", path.display()));
+    }
+    if UnixStream::connect(path).await.is_ok() {
+        return Err(anyhow!("{} is already in use", path.display()));
+    }
+
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+fn create_fifo(path: &Path) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+
+    // SAFETY: c_path is a valid NUL-terminated string that outlives the call
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to create named pipe {}", path.display()));
+    }
+
+    log::info!("Created named pipe: {}", path.display());
+    Ok(())
+}
+
+/// Removes a listening socket's file when the listener goes away
+struct SocketFile<'a>(&'a Path);
+
+impl Drop for SocketFile<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(self.0) {
+            log::debug!("Failed to remove {}: {}", self.0.display(), e);
+        }
+    }
+}
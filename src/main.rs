@@ -1,9 +1,23 @@
 use openaction::OpenActionResult;
 
+mod auth;
+mod expression;
+mod extract;
 mod gfx;
 mod graph_data;
+mod history;
+mod levels;
+mod local;
 mod plugin;
+mod push;
 mod sensors;
+mod smoothing;
+mod source;
+mod sse;
+mod store;
+mod template;
+mod tls;
+mod units;
 mod websocket;
 
 #[tokio::main]
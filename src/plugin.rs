@@ -2,48 +2,137 @@ use anyhow::Result;
 use lazy_static::lazy_static;
 use openaction::*;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
 use tokio::time::{interval, Duration};
 
+use crate::expression::{Expression, Input};
 use crate::graph_data::{DataSource, GraphData, GraphSettings, MetricType, VisualizationType};
 use crate::sensors;
-use crate::websocket::WebSocketClient;
+use crate::source::ConnectionState;
+use crate::store;
 
+/// Base tick, keys with a longer sample interval update on every nth tick
 const UPDATE_INTERVAL_SECS: u64 = 1;
 
+/// How often changed histories are written to disk
+const HISTORY_FLUSH_INTERVAL_SECS: u64 = 60;
+
+/// How often stored histories are checked against the retention and size limits
+const STORE_PRUNE_INTERVAL_SECS: u64 = 60 * 60;
+
+/// How long a key keeps recording while hidden. Deleting a key looks the same
+/// as switching away from its page, so deleted keys would otherwise record
+/// until the plugin restarts.
+const HIDDEN_RECORDING_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
 lazy_static! {
     static ref GRAPH_INSTANCES: Arc<Mutex<HashMap<String, GraphData>>> =
         Arc::new(Mutex::new(HashMap::new()));
+
+    /// Latest value of every key with a reference name, read by expressions
+    static ref NAMED_VALUES: Mutex<HashMap<String, f32>> = Mutex::new(HashMap::new());
+
+    /// Metrics read during the current tick, shared by every key, series and
+    /// expression reading them. Load and rate metrics compare against their
+    /// previous reading, so reading them twice in a tick would give a rate over
+    /// microseconds.
+    static ref TICK_READINGS: Mutex<HashMap<(MetricType, u32), f32>> =
+        Mutex::new(HashMap::new());
 }
 
-async fn read_sensor_value(
-    settings: &GraphSettings,
-    ws_client: Option<&Arc<WebSocketClient>>,
-) -> Result<f32> {
+/// Read the current value, or `None` if a remote source hasn't delivered one
+/// yet or an expression is missing inputs
+async fn read_sensor_value(graph_data: &GraphData) -> Result<Option<f32>> {
+    let settings = &graph_data.settings;
+    let fan_number = settings.fan_number.unwrap_or(1);
+
     match settings.data_source {
-        DataSource::LmSensors => read_lm_sensors_value(settings).await,
-        DataSource::WebSocket => {
-            if let Some(client) = ws_client {
-                Ok(client.get_value().await)
-            } else {
-                Ok(0.0)
+        DataSource::LmSensors => read_metric(settings.metric_type, fan_number)
+            .await
+            .map(Some),
+        DataSource::WebSocket | DataSource::Sse | DataSource::Local | DataSource::Push => {
+            match graph_data.get_subscription() {
+                Some(subscription) if subscription.has_value().await => {
+                    Ok(Some(subscription.get_value().await))
+                }
+                _ => Ok(None),
             }
         }
+        DataSource::Expression => match graph_data.get_expression() {
+            Some(expression) => Ok(read_expression_value(expression, fan_number).await),
+            None => Ok(None),
+        },
+    }
+}
+
+/// Evaluate an expression over the current metrics and named keys' latest values
+async fn read_expression_value(expression: &Expression, fan_number: u32) -> Option<f32> {
+    let mut values = HashMap::new();
+
+    for input in expression.inputs() {
+        let value = match &input {
+            Input::Metric(metric_type) => match read_metric(*metric_type, fan_number).await {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    log::debug!("Expression input {:?} unavailable: {}", metric_type, e);
+                    None
+                }
+            },
+            Input::Named(name) => NAMED_VALUES.lock().await.get(name).copied(),
+        };
+
+        if let Some(value) = value {
+            values.insert(input, value);
+        }
     }
+
+    expression.evaluate(&values)
+}
+
+/// Make a key's latest value available to expressions, or withdraw it
+async fn publish_named_value(graph_data: &GraphData, value: Option<f32>) {
+    let Some(name) = graph_data.reference_name() else {
+        return;
+    };
+
+    let mut named_values = NAMED_VALUES.lock().await;
+    match value {
+        Some(value) => named_values.insert(name, value),
+        None => named_values.remove(&name),
+    };
 }
 
-async fn read_lm_sensors_value(settings: &GraphSettings) -> Result<f32> {
-    match settings.metric_type {
+/// Read a metric once per tick, handing later readers in the same tick the same value
+async fn read_metric(metric_type: MetricType, fan_number: u32) -> Result<f32> {
+    // Only fans are told apart by their number
+    let fan_number = match metric_type {
+        MetricType::SystemFan => fan_number,
+        _ => 0,
+    };
+
+    let mut readings = TICK_READINGS.lock().await;
+    if let Some(&value) = readings.get(&(metric_type, fan_number)) {
+        return Ok(value);
+    }
+
+    let value = read_lm_sensors_value(metric_type, fan_number).await?;
+    readings.insert((metric_type, fan_number), value);
+    Ok(value)
+}
+
+async fn read_lm_sensors_value(metric_type: MetricType, fan_number: u32) -> Result<f32> {
+    match metric_type {
         MetricType::CpuTemp | MetricType::CpuPackageTemp => sensors::find_cpu_temperature().await,
         MetricType::CpuLoad => sensors::find_cpu_load().await,
         MetricType::GpuTemp => sensors::find_gpu_temperature().await,
         MetricType::GpuLoad => sensors::find_gpu_load().await,
+        MetricType::GpuPower => sensors::find_gpu_power().await,
         MetricType::MotherboardTemp => sensors::find_motherboard_temperature().await,
         MetricType::NvmeTemp => sensors::find_nvme_temperature().await,
-        MetricType::SystemFan => {
-            sensors::find_system_fan_speed(settings.fan_number.unwrap_or(1)).await
-        }
+        MetricType::SystemFan => sensors::find_system_fan_speed(fan_number).await,
         MetricType::CpuVoltage => sensors::find_cpu_voltage().await,
         MetricType::DiskWrite => sensors::find_disk_write().await,
         MetricType::DiskRead => sensors::find_disk_read().await,
@@ -54,6 +143,21 @@ async fn read_lm_sensors_value(settings: &GraphSettings) -> Result<f32> {
     }
 }
 
+async fn prune_store() {
+    if let Err(e) = store::prune().await {
+        log::warn!("Failed to prune stored histories: {:#}", e);
+    }
+}
+
+/// Write history snapshots taken while the instances were locked
+async fn save_histories(snapshots: Vec<(PathBuf, Vec<u8>)>) {
+    for (path, snapshot) in snapshots {
+        if let Err(e) = store::save(&path, &snapshot).await {
+            log::warn!("Failed to save history: {:#}", e);
+        }
+    }
+}
+
 pub struct GraphAction;
 
 #[async_trait]
@@ -69,10 +173,21 @@ impl Action for GraphAction {
         let instance_id = instance.instance_id.clone();
         let mut instances = GRAPH_INSTANCES.lock().await;
 
+        // Keys that always record were kept while hidden and are already up to date
+        if let Some(graph_data) = instances.get_mut(&instance_id) {
+            graph_data.hidden_since = None;
+            let snapshots = apply_settings(&instance_id, graph_data, settings).await;
+            drop(instances);
+
+            save_histories(snapshots).await;
+            return Ok(());
+        }
+
         let mut graph_data = GraphData::new(settings.clone());
+        graph_data.load_history(&instance_id).await;
 
-        if let Err(e) = graph_data.initialize_websocket().await {
-            log::error!("Failed to initialize WebSocket: {}", e);
+        if let Err(e) = graph_data.initialize_source().await {
+            log::error!("Failed to initialize data source: {}", e);
         }
 
         instances.insert(instance_id, graph_data);
@@ -87,7 +202,24 @@ impl Action for GraphAction {
     ) -> OpenActionResult<()> {
         let instance_id = instance.instance_id.clone();
         let mut instances = GRAPH_INSTANCES.lock().await;
-        instances.remove(&instance_id);
+
+        let snapshots = match instances.get_mut(&instance_id) {
+            Some(graph_data) if graph_data.settings.always_record => {
+                log::debug!("Recording {} in the background", instance_id);
+                graph_data.hidden_since = Some(Instant::now());
+                graph_data.history_snapshot(&instance_id)
+            }
+            _ => match instances.remove(&instance_id) {
+                Some(mut graph_data) => {
+                    publish_named_value(&graph_data, None).await;
+                    graph_data.history_snapshot(&instance_id)
+                }
+                None => Vec::new(),
+            },
+        };
+        drop(instances);
+
+        save_histories(snapshots).await;
 
         Ok(())
     }
@@ -100,29 +232,153 @@ impl Action for GraphAction {
         let instance_id = instance.instance_id.clone();
         let mut instances = GRAPH_INSTANCES.lock().await;
 
-        if let Some(graph_data) = instances.get_mut(&instance_id) {
-            let old_source = graph_data.settings.data_source.clone();
-            graph_data.settings = settings.clone();
+        let snapshots = match instances.get_mut(&instance_id) {
+            Some(graph_data) => apply_settings(&instance_id, graph_data, settings).await,
+            None => Vec::new(),
+        };
+        drop(instances);
 
-            // Reinitialize WebSocket if source changed to WebSocket
-            if settings.data_source == DataSource::WebSocket && old_source != DataSource::WebSocket
-            {
-                if let Err(e) = graph_data.initialize_websocket().await {
-                    log::error!("Failed to initialize WebSocket: {}", e);
-                }
-            }
-        }
+        save_histories(snapshots).await;
 
         Ok(())
     }
 }
 
+/// Apply new settings to an instance, returning snapshots of the previous
+/// metric's histories to save if the key now shows a different one or other
+/// extra series
+async fn apply_settings(
+    instance_id: &str,
+    graph_data: &mut GraphData,
+    settings: &GraphSettings,
+) -> Vec<(PathBuf, Vec<u8>)> {
+    // Each metric and series keeps its own history, switch to the stored one
+    let metric_changed = graph_data.metric_changed(settings);
+    let snapshots = if metric_changed || graph_data.series_changed(settings) {
+        graph_data.history_snapshot(instance_id)
+    } else {
+        Vec::new()
+    };
+
+    // Withdrawn in case the key is renamed, the next sample publishes it again
+    publish_named_value(graph_data, None).await;
+
+    // Resubscribes (and closes unused connections) if the source settings changed
+    if let Err(e) = graph_data.update_settings(settings.clone()).await {
+        log::error!("Failed to initialize data source: {}", e);
+    }
+
+    if metric_changed {
+        graph_data.load_history(instance_id).await;
+    } else {
+        graph_data.load_series_history(instance_id).await;
+    }
+
+    snapshots
+}
+
+/// Read the current value and record it while the source is actually delivering
+/// values. `samples` is how many sample intervals the reading stands for.
+/// Returns the recorded value as the value text shows it.
+async fn record_sample(graph_data: &mut GraphData, samples: u64) -> Result<Option<f32>> {
+    let reading = read_sensor_value(graph_data).await?;
+    let (connection_state, stale) = graph_data.source_status().await;
+
+    let connected = connection_state.is_none_or(|state| state == ConnectionState::Connected);
+    let live_value = reading.filter(|_| connected && !stale);
+    publish_named_value(graph_data, live_value).await;
+
+    let Some(value) = live_value else {
+        return Ok(None);
+    };
+
+    // Extra series are read along with the key's own value so they stay aligned
+    let fan_number = graph_data.settings.fan_number.unwrap_or(1);
+    let mut series_values = Vec::new();
+    for expression in graph_data.series_expressions() {
+        series_values.push(match expression {
+            Some(expression) => read_expression_value(expression, fan_number).await,
+            None => None,
+        });
+    }
+
+    for _ in 0..samples {
+        graph_data.add_data_point(value);
+        graph_data.add_series_points(&series_values);
+    }
+
+    Ok(graph_data.display_value())
+}
+
+/// Stop recording keys that have been hidden for longer than the TTL, closing
+/// their connections. Their history is saved and picked up if they reappear.
+async fn evict_hidden_instances() {
+    let mut instances = GRAPH_INSTANCES.lock().await;
+    let expired: Vec<String> = instances
+        .iter()
+        .filter(|(_, graph_data)| {
+            graph_data
+                .hidden_since
+                .is_some_and(|since| since.elapsed() > HIDDEN_RECORDING_TTL)
+        })
+        .map(|(instance_id, _)| instance_id.clone())
+        .collect();
+
+    let mut snapshots = Vec::new();
+    for instance_id in expired {
+        if let Some(mut graph_data) = instances.remove(&instance_id) {
+            log::debug!("Stopped recording {} after being hidden", instance_id);
+            publish_named_value(&graph_data, None).await;
+            snapshots.extend(graph_data.history_snapshot(&instance_id));
+        }
+    }
+    drop(instances);
+
+    save_histories(snapshots).await;
+}
+
 pub async fn start_sensor_monitoring() {
     tokio::spawn(async {
         let mut interval = interval(Duration::from_secs(UPDATE_INTERVAL_SECS));
+        let mut tick: u64 = 0;
 
         loop {
             interval.tick().await;
+            tick += 1;
+            TICK_READINGS.lock().await.clear();
+
+            if tick.is_multiple_of(HISTORY_FLUSH_INTERVAL_SECS / UPDATE_INTERVAL_SECS) {
+                let snapshots: Vec<_> = GRAPH_INSTANCES
+                    .lock()
+                    .await
+                    .iter_mut()
+                    .flat_map(|(instance_id, graph_data)| graph_data.history_snapshot(instance_id))
+                    .collect();
+                save_histories(snapshots).await;
+
+                evict_hidden_instances().await;
+            }
+
+            if tick.is_multiple_of(STORE_PRUNE_INTERVAL_SECS / UPDATE_INTERVAL_SECS) {
+                prune_store().await;
+            }
+
+            // Hidden keys that always record are sampled at their background rate,
+            // each reading standing in for the samples skipped in between
+            for graph_data in GRAPH_INSTANCES
+                .lock()
+                .await
+                .values_mut()
+                .filter(|graph_data| graph_data.hidden_since.is_some())
+            {
+                let interval = graph_data.background_interval_secs();
+                if tick.is_multiple_of(interval / UPDATE_INTERVAL_SECS) {
+                    let samples = interval / graph_data.sample_interval_secs();
+                    if let Err(e) = record_sample(graph_data, samples).await {
+                        log::debug!("Background sample failed: {}", e);
+                    }
+                }
+            }
 
             let visible = visible_instances(GraphAction::UUID).await;
 
@@ -132,22 +388,31 @@ pub async fn start_sensor_monitoring() {
                 let mut instances = GRAPH_INSTANCES.lock().await;
 
                 if let Some(graph_data) = instances.get_mut(&instance_id) {
-                    let ws_client = graph_data.get_ws_client();
+                    if !tick
+                        .is_multiple_of(graph_data.sample_interval_secs() / UPDATE_INTERVAL_SECS)
+                    {
+                        continue;
+                    }
 
-                    if let Ok(value) = read_sensor_value(&graph_data.settings, ws_client).await {
-                        graph_data.add_data_point(value);
+                    let reading = record_sample(graph_data, 1).await;
+                    let source_unit = match graph_data.get_subscription() {
+                        Some(subscription) => subscription.get_unit().await,
+                        None => None,
+                    };
+                    let (connection_state, _) = graph_data.source_status().await;
 
-                        let config = graph_data.get_graph_config();
+                    if let Ok(live_value) = reading {
+                        let config = graph_data.get_graph_config().await;
 
                         // Prepare title text before dropping instances
                         let title_option = if graph_data.settings.show_value_text {
-                            let suffix = match graph_data.settings.data_source {
-                                DataSource::LmSensors => {
-                                    graph_data.settings.metric_type.value_suffix()
+                            match (live_value, connection_state) {
+                                (Some(value), _) => {
+                                    Some(graph_data.format_value(value, source_unit.as_deref()))
                                 }
-                                DataSource::WebSocket => "",
-                            };
-                            Some(format!("{:.1}{}", value, suffix))
+                                (None, Some(ConnectionState::Failed)) => Some("ERR".to_string()),
+                                (None, _) => Some("--".to_string()),
+                            }
                         } else {
                             None
                         };
@@ -182,6 +447,7 @@ pub async fn start_sensor_monitoring() {
 pub async fn init() -> OpenActionResult<()> {
     log::info!("Initializing Graphs plugin");
 
+    prune_store().await;
     start_sensor_monitoring().await;
     register_action(GraphAction).await;
 
@@ -0,0 +1,480 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use http::header::{
+    AUTHORIZATION, CONNECTION, HOST, ORIGIN, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE,
+};
+use http::{HeaderMap, Method, Request, Response, StatusCode, Uri};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::source::{
+    self, Backoff, ConnectionPool, ConnectionState, SharedState, Subscribers, Subscription,
+    SubscriptionConfig,
+};
+use crate::store;
+
+/// Port the push server listens on when a key doesn't set one
+pub const DEFAULT_PUSH_PORT: u16 = 9123;
+
+/// Largest value body accepted over HTTP
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// File in the plugin's data directory holding the token pushes must carry
+const TOKEN_FILE: &str = "push-token";
+
+lazy_static! {
+    static ref SERVERS: ConnectionPool<u16> = ConnectionPool::new();
+}
+
+/// An upgrade requested by a client, finished once hyper hands over the connection
+type PendingUpgrade = Arc<Mutex<Option<(String, OnUpgrade)>>>;
+
+/// Localhost endpoint that scripts push values to
+///
+/// `POST /keys/<name>` sets the value of every key following `<name>`, and a
+/// WebSocket opened on the same path sets it with every text message. Bodies
+/// are read like WebSocket text messages, so a bare number works as well as
+/// JSON picked apart with the value selector. The server only listens on the
+/// loopback interface and runs while at least one key uses it.
+///
+/// Requests must carry the install's token, kept in the `push-token` file of
+/// the plugin's data directory, as `Authorization: Bearer <token>` or
+/// `?token=<token>`. Requests from browsers (with an `Origin` header) or for
+/// another host than the loopback one are refused, so web pages can't push
+/// values or reach the server through DNS rebinding.
+pub struct PushServer;
+
+impl PushServer {
+    /// Follow pushes to `name`, starting the server on `port` if it isn't running
+    pub async fn subscribe(port: u16, subscription: SubscriptionConfig) -> Subscription {
+        let name = format!("push server on port {}", port);
+        SERVERS
+            .subscribe(port, &name, subscription, move |subscribers, state| {
+                Self::run_server(port, subscribers, state)
+            })
+            .await
+    }
+
+    async fn run_server(port: u16, subscribers: Subscribers, state: SharedState) {
+        let mut backoff = Backoff::default();
+
+        loop {
+            let result = Self::listen(port, &subscribers, &state).await;
+            let delay = backoff.finish("Push server", &state, result).await;
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn listen(port: u16, subscribers: &Subscribers, state: &SharedState) -> Result<()> {
+        let token: Arc<str> = load_token().await?.into();
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("Failed to listen on {}", address))?;
+
+        log::info!("Push server listening on http://{}/keys/", address);
+        *state.lock().await = ConnectionState::Connected;
+
+        // Clients are aborted along with the listener when the last key goes away
+        let mut clients = JoinSet::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, peer) = accepted?;
+                    log::debug!("Push client connected from {}", peer);
+                    clients.spawn(serve_client(stream, subscribers.clone(), token.clone()));
+                }
+                Some(_) = clients.join_next() => {}
+            }
+        }
+    }
+}
+
+/// Serve HTTP requests on one client connection, then take over the
+/// connection as a WebSocket if the client asked for one
+async fn serve_client(stream: TcpStream, subscribers: Subscribers, token: Arc<str>) {
+    let pending: PendingUpgrade = Arc::default();
+
+    let service = service_fn(|request| {
+        handle_request(request, subscribers.clone(), pending.clone(), token.clone())
+    });
+    if let Err(e) = http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), service)
+        .with_upgrades()
+        .await
+    {
+        log::debug!("Push client error: {}", e);
+        return;
+    }
+
+    let upgrade = pending.lock().ok().and_then(|mut pending| pending.take());
+    if let Some((name, on_upgrade)) = upgrade {
+        match on_upgrade.await {
+            Ok(upgraded) => {
+                let socket =
+                    WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None)
+                        .await;
+                read_websocket(socket, &name, &subscribers).await;
+            }
+            Err(e) => log::debug!("Push WebSocket upgrade failed: {}", e),
+        }
+    }
+}
+
+async fn handle_request(
+    mut request: Request<Incoming>,
+    subscribers: Subscribers,
+    pending: PendingUpgrade,
+    token: Arc<str>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if !is_local_request(request.headers()) {
+        return Ok(respond(
+            StatusCode::FORBIDDEN,
+            "Only local scripts may push\n",
+        ));
+    }
+    if !is_authorized(request.headers(), request.uri(), &token) {
+        return Ok(respond(
+            StatusCode::UNAUTHORIZED,
+            "Pass the push token as a bearer token or ?token=\n",
+        ));
+    }
+
+    let Some(segment) = request
+        .uri()
+        .path()
+        .strip_prefix("/keys/")
+        .filter(|name| !name.is_empty() && !name.contains('/'))
+    else {
+        return Ok(respond(StatusCode::NOT_FOUND, "Use /keys/<name>\n"));
+    };
+    let Some(name) = percent_decode(segment) else {
+        return Ok(respond(
+            StatusCode::BAD_REQUEST,
+            "Key names must be percent-encoded UTF-8\n",
+        ));
+    };
+
+    match *request.method() {
+        Method::POST | Method::PUT => {
+            let body = match Limited::new(request.into_body(), MAX_BODY_BYTES)
+                .collect()
+                .await
+            {
+                Ok(body) => body.to_bytes(),
+                Err(e) => return Ok(respond(StatusCode::BAD_REQUEST, &format!("{}\n", e))),
+            };
+            let text = String::from_utf8_lossy(&body);
+
+            if push(&subscribers, &name, text.trim()).await {
+                Ok(respond(StatusCode::NO_CONTENT, ""))
+            } else {
+                Ok(respond(
+                    StatusCode::NOT_FOUND,
+                    &format!("No key is following '{}'\n", name),
+                ))
+            }
+        }
+        Method::GET => {
+            let Some(key) = request.headers().get(SEC_WEBSOCKET_KEY) else {
+                return Ok(respond(
+                    StatusCode::BAD_REQUEST,
+                    "POST a value or open a WebSocket\n",
+                ));
+            };
+            let accept = derive_accept_key(key.as_bytes());
+
+            if let Ok(mut pending) = pending.lock() {
+                *pending = Some((name, hyper::upgrade::on(&mut request)));
+            }
+
+            let mut response = respond(StatusCode::SWITCHING_PROTOCOLS, "");
+            let headers = response.headers_mut();
+            headers.insert(UPGRADE, http::HeaderValue::from_static("websocket"));
+            headers.insert(CONNECTION, http::HeaderValue::from_static("Upgrade"));
+            if let Ok(accept) = accept.parse() {
+                headers.insert(SEC_WEBSOCKET_ACCEPT, accept);
+            }
+            Ok(response)
+        }
+        _ => Ok(respond(StatusCode::METHOD_NOT_ALLOWED, "")),
+    }
+}
+
+async fn read_websocket<S>(mut socket: WebSocketStream<S>, name: &str, subscribers: &Subscribers)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    log::debug!("Push WebSocket opened for '{}'", name);
+
+    while let Some(message) = socket.next().await {
+        match message {
+            Ok(Message::Text(text)) => {
+                if !push(subscribers, name, text.trim()).await {
+                    log::debug!("No key is following '{}'", name);
+                }
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(e) => {
+                log::debug!("Push WebSocket error: {}", e);
+                break;
+            }
+        }
+    }
+
+    log::debug!("Push WebSocket closed for '{}'", name);
+}
+
+/// Hand a pushed value to every key following `name`, returning whether there were any
+async fn push(subscribers: &Subscribers, name: &str, text: &str) -> bool {
+    let json = serde_json::from_str::<Value>(text).ok();
+    let mut delivered = false;
+
+    for subscriber in source::live_subscribers(subscribers).await {
+        if subscriber.config.push_name.as_deref() == Some(name) {
+            subscriber.apply_text_message(text, json.as_ref()).await;
+            delivered = true;
+        }
+    }
+
+    delivered
+}
+
+/// Refuse browser requests and requests for a host name that isn't loopback,
+/// which is what a DNS rebinding attack would send
+fn is_local_request(headers: &HeaderMap) -> bool {
+    if headers.contains_key(ORIGIN) {
+        return false;
+    }
+
+    let Some(host) = headers.get(HOST) else {
+        return true;
+    };
+    let Some(host) = host.to_str().ok().map(strip_port) else {
+        return false;
+    };
+
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Host of a `Host` header without its port, keeping IPv6 brackets intact
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.ends_with(':') && port.bytes().all(|b| b.is_ascii_digit()) => {
+            name
+        }
+        _ => host,
+    }
+}
+
+fn is_authorized(headers: &HeaderMap, uri: &Uri, token: &str) -> bool {
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    let query = uri.query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+    });
+
+    bearer
+        .or(query)
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+/// Compare without stopping at the first difference, so response times don't
+/// reveal how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Decode `%XX` escapes in a path segment, `None` if they are malformed or not UTF-8
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte != b'%' {
+            bytes.push(byte);
+            rest = tail;
+            continue;
+        }
+
+        let hex = tail
+            .get(..2)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+        bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+        rest = &tail[2..];
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+/// Read the install's push token, creating it on first use
+async fn load_token() -> Result<String> {
+    let path = store::data_dir()?.join(TOKEN_FILE);
+
+    match tokio::fs::read_to_string(&path).await {
+        Ok(token) if !token.trim().is_empty() => return Ok(token.trim().to_string()),
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    }
+
+    let token: String = {
+        let mut rng = rand::thread_rng();
+        (0..32)
+            .map(|_| format!("{:x}", rng.gen_range(0..16)))
+            .collect()
+    };
+    write_token(&path, &token)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    log::info!("Created push token in {}", path.display());
+    Ok(token)
+}
+
+/// Write the token readable by the user only
+async fn write_token(path: &Path, token: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true).mode(0o600);
+    let mut file = options.open(path).await?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, format!("{}\n", token).as_bytes()).await
+}
+
+fn respond(status: StatusCode, body: &str) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body.to_string())));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn headers(pairs: &[(http::header::HeaderName, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn only_local_requests_are_accepted() {
+        for host in [
+            "localhost:9123",
+            "LOCALHOST",
+            "127.0.0.1:9123",
+            "[::1]:9123",
+            "::1",
+        ] {
+            assert!(is_local_request(&headers(&[(HOST, host)])), "{}", host);
+        }
+        assert!(is_local_request(&headers(&[])));
+
+        // A page served from elsewhere, even when it targets the loopback address
+        assert!(!is_local_request(&headers(&[
+            (HOST, "localhost:9123"),
+            (ORIGIN, "https://evil.example"),
+        ])));
+        assert!(!is_local_request(&headers(&[(ORIGIN, "null")])));
+
+        // A rebound DNS name resolves to 127.0.0.1 but keeps its own Host
+        for host in [
+            "evil.example:9123",
+            "localhost.evil.example",
+            "127.0.0.1.nip.io",
+        ] {
+            assert!(!is_local_request(&headers(&[(HOST, host)])), "{}", host);
+        }
+    }
+
+    #[test]
+    fn ports_are_stripped_from_hosts() {
+        assert_eq!(strip_port("localhost:9123"), "localhost");
+        assert_eq!(strip_port("localhost"), "localhost");
+        assert_eq!(strip_port("[::1]:9123"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+        assert_eq!(strip_port("::1"), "::1");
+    }
+
+    #[test]
+    fn requests_need_the_token() {
+        let token = "0123456789abcdef";
+        let uri = |uri: &str| uri.parse::<Uri>().unwrap();
+        let bearer = |value: &str| headers(&[(AUTHORIZATION, value)]);
+
+        assert!(is_authorized(
+            &bearer("Bearer 0123456789abcdef"),
+            &uri("/keys/a"),
+            token
+        ));
+        assert!(is_authorized(
+            &headers(&[]),
+            &uri("/keys/a?x=1&token=0123456789abcdef"),
+            token
+        ));
+
+        assert!(!is_authorized(&headers(&[]), &uri("/keys/a"), token));
+        assert!(!is_authorized(
+            &bearer("Bearer 0123456789abcdee"),
+            &uri("/keys/a"),
+            token
+        ));
+        assert!(!is_authorized(
+            &bearer("Bearer 0123456789abcde"),
+            &uri("/keys/a"),
+            token
+        ));
+        assert!(!is_authorized(
+            &bearer("Basic 0123456789abcdef"),
+            &uri("/keys/a"),
+            token
+        ));
+        assert!(!is_authorized(&headers(&[]), &uri("/keys/a?token="), token));
+    }
+
+    #[test]
+    fn key_names_are_percent_decoded() {
+        assert_eq!(percent_decode("cpu").as_deref(), Some("cpu"));
+        assert_eq!(percent_decode("room%20temp").as_deref(), Some("room temp"));
+        assert_eq!(percent_decode("k%C3%BCche").as_deref(), Some("küche"));
+        assert_eq!(percent_decode("50%").as_deref(), None);
+        assert_eq!(percent_decode("%zz").as_deref(), None);
+        assert_eq!(percent_decode("%FF").as_deref(), None);
+    }
+}
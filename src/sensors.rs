@@ -3,13 +3,17 @@ use lm_sensors as sensors;
 use std::fs;
 use std::path::Path;
 use std::sync::Mutex;
+use std::time::Instant;
 use sysinfo::{Components, Disks, Networks, System};
 
 static PREV_CPU_STATS: Mutex<Option<(u64, u64)>> = Mutex::new(None);
-static PREV_DISK_WRITE: Mutex<Option<u64>> = Mutex::new(None);
-static PREV_DISK_READ: Mutex<Option<u64>> = Mutex::new(None);
-static PREV_NET_RX: Mutex<Option<u64>> = Mutex::new(None);
-static PREV_NET_TX: Mutex<Option<u64>> = Mutex::new(None);
+/// Previous reading of a byte counter and when it was taken
+type Counter = Mutex<Option<(u64, Instant)>>;
+
+static PREV_DISK_WRITE: Counter = Mutex::new(None);
+static PREV_DISK_READ: Counter = Mutex::new(None);
+static PREV_NET_RX: Counter = Mutex::new(None);
+static PREV_NET_TX: Counter = Mutex::new(None);
 
 #[derive(Debug, Clone, Copy)]
 enum GpuVendor {
@@ -125,16 +129,7 @@ pub async fn find_disk_write() -> Result<f32> {
         total_written += disk.usage().total_written_bytes;
     }
 
-    let mut prev = PREV_DISK_WRITE.lock().unwrap();
-    let write_speed = if let Some(prev_written) = *prev {
-        let delta = total_written.saturating_sub(prev_written);
-        (delta as f32) / 1_048_576.0 // Convert to MB/s
-    } else {
-        0.0
-    };
-
-    *prev = Some(total_written);
-    Ok(write_speed)
+    Ok(counter_rate(&PREV_DISK_WRITE, total_written))
 }
 
 /// Find disk read speed in MB/s
@@ -147,16 +142,7 @@ pub async fn find_disk_read() -> Result<f32> {
         total_read += disk.usage().total_read_bytes;
     }
 
-    let mut prev = PREV_DISK_READ.lock().unwrap();
-    let read_speed = if let Some(prev_read) = *prev {
-        let delta = total_read.saturating_sub(prev_read);
-        (delta as f32) / 1_048_576.0 // Convert to MB/s
-    } else {
-        0.0
-    };
-
-    *prev = Some(total_read);
-    Ok(read_speed)
+    Ok(counter_rate(&PREV_DISK_READ, total_read))
 }
 
 /// Find network download speed in MB/s
@@ -168,16 +154,7 @@ pub async fn find_net_download() -> Result<f32> {
         total_rx += network.total_received();
     }
 
-    let mut prev = PREV_NET_RX.lock().unwrap();
-    let download_speed = if let Some(prev_rx) = *prev {
-        let delta = total_rx.saturating_sub(prev_rx);
-        (delta as f32) / 1_048_576.0 // Convert to MB/s
-    } else {
-        0.0
-    };
-
-    *prev = Some(total_rx);
-    Ok(download_speed)
+    Ok(counter_rate(&PREV_NET_RX, total_rx))
 }
 
 /// Find network upload speed in MB/s
@@ -189,16 +166,7 @@ pub async fn find_net_upload() -> Result<f32> {
         total_tx += network.total_transmitted();
     }
 
-    let mut prev = PREV_NET_TX.lock().unwrap();
-    let upload_speed = if let Some(prev_tx) = *prev {
-        let delta = total_tx.saturating_sub(prev_tx);
-        (delta as f32) / 1_048_576.0 // Convert to MB/s
-    } else {
-        0.0
-    };
-
-    *prev = Some(total_tx);
-    Ok(upload_speed)
+    Ok(counter_rate(&PREV_NET_TX, total_tx))
 }
 /// Find CPU temperature from lm-sensors
 pub async fn find_cpu_temperature() -> Result<f32> {
@@ -255,6 +223,41 @@ pub async fn find_gpu_load() -> Result<f32> {
     Ok(0.0)
 }
 
+/// Find GPU power draw in watts
+pub async fn find_gpu_power() -> Result<f32> {
+    match detect_gpu_vendor() {
+        GpuVendor::Amd => {
+            // AMD GPU power from sysfs hwmon, in microwatts
+            let hwmon_path = "/sys/class/drm/card0/device/hwmon";
+            if let Ok(entries) = fs::read_dir(hwmon_path) {
+                for entry in entries.flatten() {
+                    for file in ["power1_average", "power1_input"] {
+                        if let Ok(power_str) = fs::read_to_string(entry.path().join(file)) {
+                            if let Ok(power_micros) = power_str.trim().parse::<f32>() {
+                                return Ok(power_micros / 1_000_000.0);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        GpuVendor::Nvidia => {
+            // NVIDIA GPU power using NVML, in milliwatts
+            if let Ok(nvml) = nvml_wrapper::Nvml::init() {
+                if let Ok(device) = nvml.device_by_index(0) {
+                    if let Ok(power) = device.power_usage() {
+                        return Ok(power as f32 / 1000.0);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+
+    log::warn!("GPU power sensor not found");
+    Ok(0.0)
+}
+
 /// Find GPU temperature from lm-sensors
 pub async fn find_gpu_temperature() -> Result<f32> {
     match detect_gpu_vendor() {
@@ -386,3 +389,25 @@ pub async fn find_cpu_voltage() -> Result<f32> {
     }
     Ok(0.0)
 }
+
+/// Rate of a byte counter since its previous reading in MB/s, 0 on the first reading
+fn counter_rate(prev: &Counter, total: u64) -> f32 {
+    let now = Instant::now();
+    let mut prev = prev.lock().unwrap();
+
+    let rate = match *prev {
+        Some((prev_total, prev_time)) => {
+            let elapsed = now.duration_since(prev_time).as_secs_f32();
+            let delta = total.saturating_sub(prev_total) as f32;
+            if elapsed > 0.0 {
+                delta / elapsed / 1_000_000.0
+            } else {
+                0.0
+            }
+        }
+        None => 0.0,
+    };
+
+    *prev = Some((total, now));
+    rate
+}
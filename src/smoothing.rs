@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+
+use crate::graph_data::Smoothing;
+
+/// Smooths a stream of readings with one of the `Smoothing` filters
+pub struct Smoother {
+    mode: Smoothing,
+    alpha: f32,
+    samples: usize,
+    window: VecDeque<f32>,
+    average: Option<f32>,
+}
+
+impl Smoother {
+    /// `alpha` weighs new readings for the EMA, `samples` is the window of the
+    /// moving average and median
+    pub fn new(mode: Smoothing, alpha: f32, samples: usize) -> Self {
+        Self {
+            mode,
+            alpha: alpha.clamp(0.01, 1.0),
+            samples: samples.max(1),
+            window: VecDeque::new(),
+            average: None,
+        }
+    }
+
+    /// Feed a reading and get the smoothed value
+    pub fn apply(&mut self, value: f32) -> f32 {
+        match self.mode {
+            Smoothing::None => value,
+            Smoothing::Ema => {
+                let average = match self.average {
+                    Some(average) => average + self.alpha * (value - average),
+                    None => value,
+                };
+                *self.average.insert(average)
+            }
+            Smoothing::Average => {
+                self.push(value);
+                self.window.iter().sum::<f32>() / self.window.len() as f32
+            }
+            Smoothing::Median => {
+                self.push(value);
+                let mut sorted: Vec<f32> = self.window.iter().copied().collect();
+                sorted.sort_by(f32::total_cmp);
+
+                let middle = sorted.len() / 2;
+                if sorted.len().is_multiple_of(2) {
+                    (sorted[middle - 1] + sorted[middle]) / 2.0
+                } else {
+                    sorted[middle]
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, value: f32) {
+        if self.window.len() >= self.samples {
+            self.window.pop_front();
+        }
+        self.window.push_back(value);
+    }
+}
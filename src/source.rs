@@ -0,0 +1,394 @@
+use rand::Rng;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Weak};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+
+use crate::extract::{self, MessageFilter, TextExtractor, ValueSelector};
+
+/// Reconnect delays grow exponentially from the initial delay up to the cap,
+/// with random jitter so keys sharing a server don't reconnect in lockstep
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Shortest initial delay a server may ask for, so a `retry: 0` doesn't spin
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(100);
+const RECONNECT_JITTER: f64 = 0.25;
+
+/// State of a remote connection, exposed so keys can show a status indicator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// First connection attempt is in progress
+    Connecting,
+    /// Connected and past the initialization handshake
+    Connected,
+    /// Connection lost or failed, waiting before the next attempt
+    Disconnected,
+    /// Initialization was rejected or timed out, waiting before the next attempt
+    Failed,
+}
+
+/// The server rejected the connection during initialization, or never answered
+#[derive(Debug)]
+pub struct InitFailed(pub String);
+
+impl std::fmt::Display for InitFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Initialization failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for InitFailed {}
+
+/// What a single key reads from a (possibly shared) connection
+#[derive(Clone)]
+pub struct SubscriptionConfig {
+    /// Home Assistant entity to follow on a shared connection
+    pub entity_id: Option<String>,
+    /// Name values are pushed to on the push server
+    pub push_name: Option<String>,
+    pub selector: Option<ValueSelector>,
+    pub filter: Option<MessageFilter>,
+    /// Extraction for plain-text messages, `None` to read them as JSON
+    pub text: Option<TextExtractor>,
+}
+
+/// Latest value extracted for one subscription
+pub struct Subscriber {
+    pub config: SubscriptionConfig,
+    current_value: Mutex<f32>,
+    unit: Mutex<Option<String>>,
+    last_update: Mutex<Option<Instant>>,
+    /// When the key subscribed, what staleness counts from until a value arrives
+    subscribed_at: Instant,
+}
+
+pub type Subscribers = Arc<Mutex<Vec<Weak<Subscriber>>>>;
+pub type SharedState = Arc<Mutex<ConnectionState>>;
+
+/// A key's view of a shared connection
+pub struct Subscription {
+    subscriber: Arc<Subscriber>,
+    connection: Arc<SharedConnection>,
+}
+
+impl Subscription {
+    /// Get the current value
+    pub async fn get_value(&self) -> f32 {
+        *self.subscriber.current_value.lock().await
+    }
+
+    /// Get the unit of measurement reported by the source, if any
+    pub async fn get_unit(&self) -> Option<String> {
+        self.subscriber.unit.lock().await.clone()
+    }
+
+    /// Whether a value has been received at all
+    pub async fn has_value(&self) -> bool {
+        self.subscriber.last_update.lock().await.is_some()
+    }
+
+    /// Whether the last value, or the subscription if no value arrived yet,
+    /// is older than the given timeout
+    pub async fn is_stale(&self, timeout: Duration) -> bool {
+        self.subscriber
+            .last_update
+            .lock()
+            .await
+            .unwrap_or(self.subscriber.subscribed_at)
+            .elapsed()
+            > timeout
+    }
+
+    /// Get the state of the underlying connection
+    pub async fn connection_state(&self) -> ConnectionState {
+        *self.connection.state.lock().await
+    }
+}
+
+/// Connection task shared by every subscription with the same settings
+///
+/// The task lives exactly as long as the connection: dropping the last
+/// subscription drops the connection, which stops the task and closes the socket.
+pub struct SharedConnection {
+    name: String,
+    subscribers: Subscribers,
+    state: SharedState,
+    task: JoinHandle<()>,
+}
+
+impl Drop for SharedConnection {
+    fn drop(&mut self) {
+        log::info!("Closing connection to {}", self.name);
+        self.task.abort();
+    }
+}
+
+/// Open connections of one kind, keyed by everything that identifies the remote stream.
+/// Entries are weak so a connection goes away with its last subscription.
+pub struct ConnectionPool<K> {
+    connections: Mutex<HashMap<K, Weak<SharedConnection>>>,
+}
+
+impl<K: Clone + Eq + Hash> ConnectionPool<K> {
+    pub fn new() -> Self {
+        Self {
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Subscribe to the connection for `key`, reusing an open one if possible and
+    /// otherwise spawning `run` to drive a new one
+    pub async fn subscribe<F, Fut>(
+        &self,
+        key: K,
+        name: &str,
+        subscription: SubscriptionConfig,
+        run: F,
+    ) -> Subscription
+    where
+        F: FnOnce(Subscribers, SharedState) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut connections = self.connections.lock().await;
+        connections.retain(|_, connection| connection.strong_count() > 0);
+
+        let connection = match connections.get(&key).and_then(Weak::upgrade) {
+            Some(connection) => {
+                log::debug!("Reusing connection to {}", name);
+                connection
+            }
+            None => {
+                let subscribers: Subscribers = Arc::new(Mutex::new(Vec::new()));
+                let state = Arc::new(Mutex::new(ConnectionState::Connecting));
+                let task = tokio::spawn(run(subscribers.clone(), state.clone()));
+
+                let connection = Arc::new(SharedConnection {
+                    name: name.to_string(),
+                    subscribers,
+                    state,
+                    task,
+                });
+                connections.insert(key, Arc::downgrade(&connection));
+                connection
+            }
+        };
+
+        let subscriber = Arc::new(Subscriber {
+            config: subscription,
+            current_value: Mutex::new(0.0),
+            unit: Mutex::new(None),
+            last_update: Mutex::new(None),
+            subscribed_at: Instant::now(),
+        });
+        connection
+            .subscribers
+            .lock()
+            .await
+            .push(Arc::downgrade(&subscriber));
+
+        Subscription {
+            subscriber,
+            connection,
+        }
+    }
+}
+
+/// Reconnect backoff for one connection
+pub struct Backoff {
+    attempt: u32,
+    initial: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            attempt: 0,
+            initial: RECONNECT_INITIAL_DELAY,
+        }
+    }
+}
+
+impl Backoff {
+    /// Use a server-provided delay as the starting point, kept within the
+    /// minimum and maximum delays
+    pub fn set_initial(&mut self, initial: Duration) {
+        self.initial = initial.clamp(RECONNECT_MIN_DELAY, RECONNECT_MAX_DELAY);
+    }
+
+    /// Record how a connection attempt ended, update the connection state and
+    /// return how long to wait before the next attempt
+    pub async fn finish(
+        &mut self,
+        name: &str,
+        state: &SharedState,
+        result: anyhow::Result<()>,
+    ) -> Duration {
+        // A connection that got through its handshake starts the backoff over
+        let mut state = state.lock().await;
+        if *state == ConnectionState::Connected {
+            self.attempt = 0;
+        }
+        *state = match &result {
+            Err(e) if e.is::<InitFailed>() => ConnectionState::Failed,
+            _ => ConnectionState::Disconnected,
+        };
+        drop(state);
+
+        let delay = self.delay();
+        self.attempt = self.attempt.saturating_add(1);
+
+        match result {
+            Ok(_) => log::info!(
+                "{} connection closed, reconnecting in {:.1} seconds...",
+                name,
+                delay.as_secs_f32()
+            ),
+            Err(e) => log::error!(
+                "{} error: {:#}, reconnecting in {:.1} seconds...",
+                name,
+                e,
+                delay.as_secs_f32()
+            ),
+        }
+
+        delay
+    }
+
+    /// Delay before the current attempt
+    fn delay(&self) -> Duration {
+        let base = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(RECONNECT_MAX_DELAY);
+        let jitter = rand::thread_rng().gen_range(1.0 - RECONNECT_JITTER..=1.0 + RECONNECT_JITTER);
+        base.mul_f64(jitter)
+    }
+}
+
+/// Snapshot the live subscribers, forgetting any whose key has gone away
+pub async fn live_subscribers(subscribers: &Subscribers) -> Vec<Arc<Subscriber>> {
+    let mut subscribers = subscribers.lock().await;
+    subscribers.retain(|s| s.strong_count() > 0);
+    subscribers.iter().filter_map(Weak::upgrade).collect()
+}
+
+/// Hand a message to every subscriber
+pub async fn dispatch(subscribers: &Subscribers, json: &Value) {
+    for subscriber in live_subscribers(subscribers).await {
+        subscriber.apply_message(json).await;
+    }
+}
+
+/// Hand a text message to every subscriber, parsing it as JSON once for
+/// those that read JSON
+pub async fn dispatch_text(subscribers: &Subscribers, text: &str) {
+    let json = serde_json::from_str::<Value>(text).ok();
+
+    for subscriber in live_subscribers(subscribers).await {
+        subscriber.apply_text_message(text, json.as_ref()).await;
+    }
+}
+
+impl Subscriber {
+    /// Update the value from a JSON message using the configured filter and
+    /// selector, falling back to guessing when no selector is set
+    pub async fn apply_message(&self, json: &Value) {
+        if let Some(filter) = &self.config.filter {
+            if !filter.matches(json) {
+                log::debug!("Message rejected by filter");
+                return;
+            }
+        }
+
+        let value = match &self.config.selector {
+            Some(selector) => selector.select_number(json),
+            None => extract::guess_value(json),
+        };
+
+        if let Some(value) = value {
+            self.set_value(value).await;
+        }
+    }
+
+    /// Update the value from a text message, read as JSON unless a text format
+    /// is configured. `json` is the message already parsed, if it is valid JSON.
+    pub async fn apply_text_message(&self, text: &str, json: Option<&Value>) {
+        match (&self.config.text, json) {
+            (Some(extractor), _) => self.apply_text(extractor, text).await,
+            (None, Some(json)) => self.apply_message(json).await,
+            (None, None) => log::debug!("Ignoring non-JSON message"),
+        }
+    }
+
+    /// Update the value and unit from a plain-text message
+    pub async fn apply_text(&self, extractor: &TextExtractor, text: &str) {
+        let Some((value, unit)) = extractor.extract(text) else {
+            log::debug!("No value found in text message");
+            return;
+        };
+
+        if unit.is_some() {
+            self.set_unit(unit).await;
+        }
+        self.set_value(value).await;
+    }
+
+    pub async fn set_unit(&self, unit: Option<String>) {
+        *self.unit.lock().await = unit;
+    }
+
+    pub async fn set_value(&self, value: f32) {
+        *self.current_value.lock().await = value;
+        *self.last_update.lock().await = Some(Instant::now());
+        log::debug!("Updated value to: {}", value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_delays_are_clamped() {
+        let mut backoff = Backoff::default();
+        backoff.set_initial(Duration::ZERO);
+        assert_eq!(backoff.initial, RECONNECT_MIN_DELAY);
+
+        backoff.set_initial(Duration::from_secs(3600));
+        assert_eq!(backoff.initial, RECONNECT_MAX_DELAY);
+
+        backoff.attempt = 20;
+        let max_jittered = RECONNECT_MAX_DELAY.mul_f64(1.0 + RECONNECT_JITTER);
+        assert!(backoff.delay() <= max_jittered);
+    }
+
+    #[tokio::test]
+    async fn silent_sources_go_stale() {
+        let pool = ConnectionPool::new();
+        let config = SubscriptionConfig {
+            entity_id: None,
+            push_name: None,
+            selector: None,
+            filter: None,
+            text: None,
+        };
+        let subscription = pool
+            .subscribe("silent", "silent source", config, |_, _| async {})
+            .await;
+        let timeout = Duration::from_millis(50);
+
+        assert!(!subscription.is_stale(timeout).await);
+        tokio::time::sleep(timeout * 2).await;
+        assert!(!subscription.has_value().await);
+        assert!(subscription.is_stale(timeout).await);
+
+        subscription.subscriber.set_value(1.0).await;
+        assert!(!subscription.is_stale(timeout).await);
+        tokio::time::sleep(timeout * 2).await;
+        assert!(subscription.is_stale(timeout).await);
+    }
+}
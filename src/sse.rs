@@ -0,0 +1,416 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use http::header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE, HOST};
+use http::{HeaderValue, Request, StatusCode, Uri};
+use http_body_util::{BodyExt, Empty};
+use hyper::client::conn::http1;
+use hyper_util::rt::TokioIo;
+use lazy_static::lazy_static;
+use tokio::task::JoinSet;
+use tokio::time::Duration;
+
+use crate::auth::AuthConfig;
+use crate::source::{
+    self, Backoff, ConnectionPool, ConnectionState, InitFailed, SharedState, Subscribers,
+    Subscription, SubscriptionConfig,
+};
+use crate::tls::{self, TlsConfig};
+
+lazy_static! {
+    static ref CONNECTIONS: ConnectionPool<SseConfig> = ConnectionPool::new();
+}
+
+/// Server-Sent Events connection configuration
+///
+/// Keys with identical connection settings share a single stream.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct SseConfig {
+    pub url: String,
+    pub auth: AuthConfig,
+    pub tls: TlsConfig,
+}
+
+/// Server-Sent Events data source client
+///
+/// Every event's data is handled like a WebSocket text message. Reconnects
+/// honour the server's `retry:` delay and resume with `Last-Event-ID`.
+pub struct SseClient;
+
+impl SseClient {
+    /// Subscribe to a stream, reusing an open one with the same config if possible
+    pub async fn subscribe(config: SseConfig, subscription: SubscriptionConfig) -> Subscription {
+        let url = config.url.clone();
+        CONNECTIONS
+            .subscribe(config.clone(), &url, subscription, |subscribers, state| {
+                Self::run_connection(config, subscribers, state)
+            })
+            .await
+    }
+
+    async fn run_connection(config: SseConfig, subscribers: Subscribers, state: SharedState) {
+        let mut backoff = Backoff::default();
+        let mut parser = EventParser::default();
+
+        loop {
+            let result = Self::connect_and_run(&config, &subscribers, &state, &mut parser).await;
+
+            if let Some(retry) = parser.retry {
+                backoff.set_initial(retry);
+            }
+            let delay = backoff.finish("SSE", &state, result).await;
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn connect_and_run(
+        config: &SseConfig,
+        subscribers: &Subscribers,
+        state: &SharedState,
+        parser: &mut EventParser,
+    ) -> Result<()> {
+        log::info!("Connecting to SSE stream: {}", config.url);
+
+        let (url, mut headers) = config.auth.apply(&config.url)?;
+        let uri: Uri = url.parse()?;
+        let authority = uri
+            .authority()
+            .ok_or_else(|| anyhow!("SSE URL has no host: {}", config.url))?;
+
+        headers.insert(HOST, authority.as_str().parse()?);
+        headers.insert(ACCEPT, HeaderValue::from_static("text/event-stream"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        if !parser.last_event_id.is_empty() {
+            headers.insert("last-event-id", parser.last_event_id.parse()?);
+        }
+
+        let path = uri.path_and_query().map_or("/", |path| path.as_str());
+        let mut request = Request::get(path).body(Empty::<Bytes>::new())?;
+        *request.headers_mut() = headers;
+
+        let stream = tls::open_stream(&uri, &config.tls).await?;
+        let (mut sender, connection) = http1::handshake(TokioIo::new(stream)).await?;
+
+        // Drive the connection on its own, so a response the server sends just before
+        // closing is still read. Its errors surface through the response, and it is
+        // aborted with the set once the session ends.
+        let mut connection_task = JoinSet::new();
+        connection_task.spawn(connection);
+
+        let session = async {
+            let response = sender.send_request(request).await?;
+            let status = response.status();
+
+            if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+                return Err(InitFailed(format!("server responded {}", status)).into());
+            }
+            if status != StatusCode::OK {
+                return Err(anyhow!("SSE server responded {}", status));
+            }
+
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            if !content_type.starts_with("text/event-stream") {
+                return Err(InitFailed(format!(
+                    "expected text/event-stream, got '{}'",
+                    content_type
+                ))
+                .into());
+            }
+
+            log::info!("SSE stream connected");
+            *state.lock().await = ConnectionState::Connected;
+            parser.reset();
+
+            let mut body = response.into_body();
+            while let Some(frame) = body.frame().await {
+                let Ok(chunk) = frame?.into_data() else {
+                    continue;
+                };
+
+                for data in parser.feed(&chunk) {
+                    log::debug!("Received event: {}", data);
+                    source::dispatch_text(subscribers, &data).await;
+                }
+            }
+
+            log::info!("SSE stream closed by server");
+            Ok(())
+        };
+
+        session.await
+    }
+}
+
+/// Incremental `text/event-stream` parser
+///
+/// The last event ID and retry delay outlive a single connection so that
+/// reconnects can resume where the stream left off.
+#[derive(Default)]
+struct EventParser {
+    buffer: Vec<u8>,
+    data: String,
+    event_id: String,
+    last_event_id: String,
+    retry: Option<Duration>,
+}
+
+impl EventParser {
+    /// Forget partial input from a previous connection
+    fn reset(&mut self) {
+        self.buffer.clear();
+        self.data.clear();
+    }
+
+    /// Feed a chunk of the stream, returning the data of every completed event
+    fn feed(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+
+        // Lines end with \r\n, \n or \r
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n' || b == b'\r') {
+            let crlf = self.buffer[end] == b'\r';
+            if crlf && end + 1 == self.buffer.len() {
+                // Wait for the next chunk to see whether a \n follows
+                break;
+            }
+            let skip = if crlf && self.buffer[end + 1] == b'\n' {
+                2
+            } else {
+                1
+            };
+
+            let line: Vec<u8> = self.buffer.drain(..end + skip).take(end).collect();
+            if let Some(data) = self.process_line(&String::from_utf8_lossy(&line)) {
+                events.push(data);
+            }
+        }
+
+        events
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<String> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        match field {
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.event_id = value.to_string(),
+            "retry" => {
+                if let Ok(millis) = value.parse::<u64>() {
+                    self.retry = Some(Duration::from_millis(millis));
+                }
+            }
+            // Event types are not used; every event is read the same way
+            _ => {}
+        }
+
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<String> {
+        self.last_event_id.clone_from(&self.event_id);
+
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let mut data = std::mem::take(&mut self.data);
+        data.pop();
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio::time::Instant;
+
+    #[test]
+    fn joins_multi_line_data() {
+        let mut parser = EventParser::default();
+        let events = parser.feed(b"data: first\ndata: second\ndata:third\n\n");
+        assert_eq!(events, ["first\nsecond\nthird"]);
+    }
+
+    #[test]
+    fn handles_lines_split_across_chunks() {
+        let mut parser = EventParser::default();
+        assert!(parser.feed(b"data: 4").is_empty());
+        assert!(parser.feed(b"2\r").is_empty());
+        assert_eq!(parser.feed(b"\n\r\n"), ["42"]);
+    }
+
+    #[test]
+    fn skips_comments_and_empty_events() {
+        let mut parser = EventParser::default();
+        let events = parser.feed(b": keepalive\n\nevent: update\ndata: 1\n\n");
+        assert_eq!(events, ["1"]);
+    }
+
+    #[test]
+    fn keeps_the_last_event_id() {
+        let mut parser = EventParser::default();
+        parser.feed(b"id: 7\ndata: a\n\n");
+        assert_eq!(parser.last_event_id, "7");
+
+        // The ID carries over to events without one, and isn't taken before dispatch
+        parser.feed(b"data: b\n\nid: 8\ndata: c\n");
+        assert_eq!(parser.last_event_id, "7");
+        parser.feed(b"\n");
+        assert_eq!(parser.last_event_id, "8");
+
+        // IDs containing NUL are ignored
+        parser.feed(b"id: 9\0\ndata: d\n\n");
+        assert_eq!(parser.last_event_id, "8");
+
+        // Partial input is dropped on reconnect, the ID is kept
+        parser.feed(b"id: 10\ndata: e");
+        parser.reset();
+        assert_eq!(parser.last_event_id, "8");
+    }
+
+    #[test]
+    fn reads_retry_delays() {
+        let mut parser = EventParser::default();
+        parser.feed(b"retry: 2500\n\n");
+        assert_eq!(parser.retry, Some(Duration::from_millis(2500)));
+
+        // Invalid values leave the previous delay
+        parser.feed(b"retry: soon\nretry: -1\n\n");
+        assert_eq!(parser.retry, Some(Duration::from_millis(2500)));
+
+        parser.feed(b"retry: 0\n");
+        assert_eq!(parser.retry, Some(Duration::ZERO));
+    }
+
+    /// Serve one canned response per connection, the last one repeating, and
+    /// report each request head with the time it arrived
+    async fn stub_server(
+        responses: Vec<String>,
+    ) -> (String, mpsc::UnboundedReceiver<(String, Instant)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        let (requests, received) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            for index in 0.. {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    break;
+                };
+
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0];
+                    if stream.read(&mut byte).await.unwrap_or(0) == 0 {
+                        break;
+                    }
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8_lossy(&head).to_lowercase();
+                let _ = requests.send((head, Instant::now()));
+
+                let response = &responses[index.min(responses.len() - 1)];
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+
+        (url, received)
+    }
+
+    fn stream_response(body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n{}",
+            body
+        )
+    }
+
+    async fn subscribe(url: String) -> Subscription {
+        let config = SseConfig {
+            url,
+            auth: AuthConfig::default(),
+            tls: TlsConfig::default(),
+        };
+        let subscription = SubscriptionConfig {
+            entity_id: None,
+            push_name: None,
+            selector: None,
+            filter: None,
+            text: None,
+        };
+        SseClient::subscribe(config, subscription).await
+    }
+
+    async fn eventually<F, Fut>(mut condition: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition().await {
+            assert!(Instant::now() < deadline, "condition not reached in time");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_events_and_resumes_after_reconnecting() {
+        let (url, mut requests) = stub_server(vec![
+            stream_response("retry: 100\nid: 7\ndata: 42\n\n"),
+            stream_response("data: {\"value\": 43}\n\n"),
+        ])
+        .await;
+        let subscription = subscribe(url).await;
+
+        let (first, first_at) = requests.recv().await.unwrap();
+        assert!(first.starts_with("get /events http/1.1\r\n"));
+        assert!(first.contains("\r\naccept: text/event-stream\r\n"));
+        assert!(!first.contains("last-event-id"));
+        eventually(|| async { subscription.get_value().await == 42.0 }).await;
+
+        // The server's retry delay replaces the default one second backoff
+        let (second, second_at) = requests.recv().await.unwrap();
+        assert!(second_at - first_at < Duration::from_millis(500));
+        assert!(second.contains("\r\nlast-event-id: 7\r\n"));
+        eventually(|| async { subscription.get_value().await == 43.0 }).await;
+    }
+
+    #[tokio::test]
+    async fn rejected_streams_fail_initialization() {
+        let responses = [
+            "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_string(),
+            "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 0\r\n\r\n".to_string(),
+        ];
+
+        for response in responses {
+            let (url, _requests) = stub_server(vec![response]).await;
+            let subscription = subscribe(url).await;
+            eventually(|| async {
+                subscription.connection_state().await == ConnectionState::Failed
+            })
+            .await;
+            assert!(!subscription.has_value().await);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::history::History;
+
+const PLUGIN_DIR: &str = "com.victormarin.graphs";
+
+/// Stored histories that haven't been written for this long are deleted
+const RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Cap on the total size of the store, the least recently written files go first
+const MAX_STORE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// A history as written to disk
+#[derive(Serialize, Deserialize)]
+struct StoredHistory {
+    /// Unix seconds when the history was written
+    saved_at: u64,
+    history: History,
+}
+
+/// The plugin's data directory, under `$XDG_DATA_HOME` or `~/.local/share`
+pub fn data_dir() -> Result<PathBuf> {
+    let data_dir = match std::env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".local/share"))
+            .ok_or_else(|| anyhow!("Neither XDG_DATA_HOME nor HOME is set"))?,
+    };
+
+    Ok(data_dir.join(PLUGIN_DIR))
+}
+
+/// Directory holding stored histories
+fn store_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("history"))
+}
+
+/// File for one instance's history of one metric
+///
+/// `metric` identifies what is graphed, so switching a key to another metric
+/// and back finds the earlier history again.
+pub fn history_path(instance_id: &str, metric: &str) -> Result<PathBuf> {
+    let instance: String = instance_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    Ok(store_dir()?.join(format!("{}-{:016x}.json", instance, fnv1a(metric))))
+}
+
+/// Load a stored history, dropping whatever has aged out of its window since
+/// it was written. Missing or unreadable files, and histories laid out
+/// differently from `expected`, load as `None`.
+pub async fn load(path: &Path, sample_interval_secs: u64, expected: &History) -> Option<History> {
+    let contents = tokio::fs::read(path).await.ok()?;
+    let stored: StoredHistory = match serde_json::from_slice(&contents) {
+        Ok(stored) => stored,
+        Err(e) => {
+            log::warn!("Ignoring unreadable history {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    // Also catches a damaged file with no samples per bucket
+    if !stored.history.has_layout_of(expected) {
+        log::warn!(
+            "Ignoring history {} recorded for another window",
+            path.display()
+        );
+        return None;
+    }
+
+    let elapsed = unix_now().saturating_sub(stored.saved_at);
+    let mut history = stored.history;
+    history.skip(elapsed.div_ceil(sample_interval_secs.max(1)) as usize);
+
+    log::debug!("Loaded history from {}", path.display());
+    Some(history)
+}
+
+/// Serialize a history, done while its key is locked so the write can happen after
+pub fn snapshot(history: &History) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&StoredHistory {
+        saved_at: unix_now(),
+        history: history.clone(),
+    })?)
+}
+
+/// Write a snapshot, replacing the previous file atomically
+pub async fn save(path: &Path, snapshot: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let temp = path.with_extension("json.tmp");
+    tokio::fs::write(&temp, snapshot)
+        .await
+        .with_context(|| format!("Failed to write {}", temp.display()))?;
+    tokio::fs::rename(&temp, path).await?;
+    Ok(())
+}
+
+/// Apply the retention policy and size cap to the store
+pub async fn prune() -> Result<()> {
+    let dir = store_dir()?;
+    let mut entries = match tokio::fs::read_dir(&dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut files = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() {
+            files.push((entry.path(), metadata.modified()?, metadata.len()));
+        }
+    }
+
+    // Newest first, so everything after the size cap is reached goes
+    files.sort_by_key(|(_, modified, _)| std::cmp::Reverse(*modified));
+
+    let mut total = 0;
+    for (path, modified, size) in files {
+        total += size;
+        let expired = modified.elapsed().is_ok_and(|age| age > RETENTION);
+
+        if expired || total > MAX_STORE_BYTES {
+            log::debug!("Removing stored history {}", path.display());
+            tokio::fs::remove_file(&path).await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+/// FNV-1a, a hash that stays the same across builds so file names do too
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn load_edited(name: &str, edit: impl FnOnce(&mut serde_json::Value)) -> Option<History> {
+        let mut history = History::new(3600, 100);
+        history.push(42.0);
+        let mut json: serde_json::Value =
+            serde_json::from_slice(&snapshot(&history).unwrap()).unwrap();
+        edit(&mut json);
+
+        let path = std::env::temp_dir().join(format!(
+            "graphs-store-test-{}-{}.json",
+            std::process::id(),
+            name
+        ));
+        tokio::fs::write(&path, json.to_string()).await.unwrap();
+        let loaded = load(&path, 1, &History::new(3600, 100)).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+        loaded
+    }
+
+    #[tokio::test]
+    async fn loads_histories_for_the_same_window() {
+        let history = load_edited("same", |_| {}).await.unwrap();
+        assert_eq!(history.buckets().len(), 1);
+        assert_eq!(history.buckets()[0].max, 42.0);
+    }
+
+    #[tokio::test]
+    async fn drops_histories_with_another_layout() {
+        let zero = load_edited("zero", |json| {
+            json["history"]["samples_per_bucket"] = 0.into()
+        });
+        assert!(zero.await.is_none());
+
+        let other_window = load_edited("window", |json| {
+            json["history"]["samples_per_bucket"] = 72.into()
+        });
+        assert!(other_window.await.is_none());
+
+        let other_capacity =
+            load_edited("capacity", |json| json["history"]["capacity"] = 50.into());
+        assert!(other_capacity.await.is_none());
+    }
+}
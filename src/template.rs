@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Context, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Renders `{{name}}` placeholders in messages sent to a remote source
+///
+/// Available variables are `{{api_key}}` (with secret references resolved),
+/// `{{env.NAME}}` for environment variables, `{{msg_id}}`, a counter that
+/// advances with every message on a connection, and `{{timestamp}}` in Unix seconds.
+/// Anything else between braces, or a `{{` that is never closed, is sent as written.
+pub struct Templates {
+    api_key: Option<String>,
+    msg_id: u64,
+}
+
+impl Templates {
+    pub fn new(api_key: Option<String>) -> Self {
+        Self { api_key, msg_id: 0 }
+    }
+
+    /// Advance `{{msg_id}}`, called once per outgoing message
+    pub fn next_message(&mut self) {
+        self.msg_id += 1;
+    }
+
+    pub fn render(&self, template: &str) -> Result<String> {
+        let mut output = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find("{{") {
+            output.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let value = match after.find("}}") {
+                Some(end) => self
+                    .variable(after[..end].trim())?
+                    .map(|value| (value, end)),
+                None => None,
+            };
+
+            match value {
+                Some((value, end)) => {
+                    output.push_str(&value);
+                    rest = &after[end + 2..];
+                }
+                // Not one of ours, keep the braces and look for placeholders after them
+                None => {
+                    output.push_str("{{");
+                    rest = after;
+                }
+            }
+        }
+
+        output.push_str(rest);
+        Ok(output)
+    }
+
+    /// Look up a variable, `None` if the name isn't one we know
+    fn variable(&self, name: &str) -> Result<Option<String>> {
+        let value = match name {
+            "api_key" => self
+                .api_key
+                .clone()
+                .ok_or_else(|| anyhow!("Message uses {{{{api_key}}}} but no API key is set"))?,
+            "msg_id" => self.msg_id.to_string(),
+            "timestamp" => SystemTime::now()
+                .duration_since(UNIX_EPOCH)?
+                .as_secs()
+                .to_string(),
+            _ => match name.strip_prefix("env.") {
+                Some(var) => std::env::var(var)
+                    .map_err(|_| anyhow!("Environment variable {} is not set", var))?,
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(value))
+    }
+}
+
+/// Resolve a secret setting so it doesn't have to live in the profile
+///
+/// `env:NAME` reads an environment variable and `file:PATH` reads the first
+/// line of a file (`~/` is expanded). Anything else is the secret itself.
+pub fn resolve_secret(value: &str) -> Result<String> {
+    if let Some(var) = value.strip_prefix("env:") {
+        return std::env::var(var.trim())
+            .map_err(|_| anyhow!("Environment variable {} is not set", var.trim()));
+    }
+
+    if let Some(path) = value.strip_prefix("file:") {
+        let path = expand_home(path.trim());
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read secret from {}", path))?;
+        return Ok(contents
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string());
+    }
+
+    Ok(value.to_string())
+}
+
+/// Expand a leading `~/` to the home directory
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(relative), Ok(home)) => format!("{}/{}", home, relative),
+        _ => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_known_variables() {
+        let mut templates = Templates::new(Some("secret".to_string()));
+        templates.next_message();
+        assert_eq!(
+            templates
+                .render(r#"{"id": {{ msg_id }}, "key": "{{api_key}}"}"#)
+                .unwrap(),
+            r#"{"id": 1, "key": "secret"}"#
+        );
+    }
+
+    #[test]
+    fn passes_other_braces_through() {
+        let templates = Templates::new(None);
+        assert_eq!(templates.render("{{other}} {{").unwrap(), "{{other}} {{");
+        assert_eq!(templates.render("{{ {{msg_id}} }}").unwrap(), "{{ 0 }}");
+    }
+
+    #[test]
+    fn fails_on_known_but_missing_values() {
+        let templates = Templates::new(None);
+        assert!(templates.render("{{api_key}}").is_err());
+        assert!(templates
+            .render("{{env.OA_GRAPHS_TEST_UNSET_VARIABLE}}")
+            .is_err());
+    }
+}
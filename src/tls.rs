@@ -0,0 +1,257 @@
+use anyhow::{anyhow, Context, Result};
+use http::Uri;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::MaybeTlsStream;
+
+/// TLS settings for an encrypted remote source
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TlsConfig {
+    /// PEM bundle of extra CAs to trust on top of the system roots
+    pub ca_path: Option<String>,
+    /// PEM client certificate chain and key for mutual TLS
+    pub client_cert_path: Option<String>,
+    pub client_key_path: Option<String>,
+    /// Name to send as SNI and verify the certificate against instead of the URL host
+    pub server_name: Option<String>,
+    /// Skip certificate verification entirely, only meant for lab hosts
+    pub insecure: bool,
+}
+
+impl TlsConfig {
+    /// Open a TLS session to `host:port` over a fresh TCP connection
+    pub async fn connect(&self, host: &str, port: u16) -> Result<TlsStream<TcpStream>> {
+        let server_name = self.server_name.as_deref().unwrap_or(host);
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|_| anyhow!("Invalid TLS server name '{}'", server_name))?;
+
+        let connector = TlsConnector::from(self.client_config()?);
+        let tcp = TcpStream::connect((host, port)).await?;
+        Ok(connector.connect(server_name, tcp).await?)
+    }
+
+    /// Build a rustls client config from these settings
+    pub fn client_config(&self) -> Result<Arc<ClientConfig>> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = if self.insecure {
+            log::warn!("TLS certificate verification is disabled");
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+        } else {
+            builder.with_root_certificates(self.root_store()?)
+        };
+
+        let config = match (&self.client_cert_path, &self.client_key_path) {
+            (Some(cert_path), Some(key_path)) => builder
+                .with_client_auth_cert(load_certs(cert_path)?, load_key(key_path)?)
+                .context("Invalid client certificate or key")?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => {
+                return Err(anyhow!(
+                    "Client certificate and key must be configured together"
+                ))
+            }
+        };
+
+        Ok(Arc::new(config))
+    }
+
+    fn root_store(&self) -> Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+
+        let native = rustls_native_certs::load_native_certs();
+        if !native.errors.is_empty() {
+            log::warn!(
+                "Failed to load some system CA certificates: {:?}",
+                native.errors
+            );
+        }
+        roots.add_parsable_certificates(native.certs);
+
+        if let Some(ca_path) = &self.ca_path {
+            for cert in load_certs(ca_path)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("Invalid CA certificate in {}", ca_path))?;
+            }
+        }
+
+        Ok(roots)
+    }
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse certificates in {}", path))?;
+
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", path));
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse private key in {}", path))?
+        .ok_or_else(|| anyhow!("No private key found in {}", path))
+}
+
+/// Accepts any server certificate, while still checking handshake signatures
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Open a connection to the host of a URL, wrapped in TLS for `wss://` and `https://`
+pub async fn open_stream(uri: &Uri, tls: &TlsConfig) -> Result<MaybeTlsStream<TcpStream>> {
+    let secure = matches!(uri.scheme_str(), Some("wss" | "https"));
+    let host = uri
+        .host()
+        .ok_or_else(|| anyhow!("URL has no host: {}", uri))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+
+    Ok(if secure {
+        MaybeTlsStream::Rustls(tls.connect(host, port).await?)
+    } else {
+        MaybeTlsStream::Plain(TcpStream::connect((host, port)).await?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::ServerConfig;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    /// Serve a greeting over TLS with a fresh self-signed certificate for
+    /// `localhost`, returning the port and the certificate as PEM
+    async fn self_signed_server() -> (u16, String) {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::try_from(key_pair.serialize_der()).unwrap();
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert.der().clone()], key)
+                .unwrap();
+
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if let Ok(mut stream) = acceptor.accept(stream).await {
+                    let _ = stream.write_all(b"hello").await;
+                    let _ = stream.shutdown().await;
+                }
+            }
+        });
+
+        (port, cert.pem())
+    }
+
+    async fn read_greeting(port: u16, tls: &TlsConfig) -> Result<String> {
+        let uri: Uri = format!("wss://localhost:{}/", port).parse()?;
+        let mut stream = open_stream(&uri, tls).await?;
+        let mut greeting = String::new();
+        stream.read_to_string(&mut greeting).await?;
+        Ok(greeting)
+    }
+
+    #[tokio::test]
+    async fn self_signed_certificates_are_rejected_by_default() {
+        let (port, _) = self_signed_server().await;
+        assert!(read_greeting(port, &TlsConfig::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn insecure_mode_accepts_self_signed_certificates() {
+        let (port, _) = self_signed_server().await;
+        let tls = TlsConfig {
+            insecure: true,
+            ..Default::default()
+        };
+        assert_eq!(read_greeting(port, &tls).await.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn ca_bundle_trusts_self_signed_certificates() {
+        let (port, pem) = self_signed_server().await;
+        let ca_path = std::env::temp_dir().join(format!("oa-graphs-test-ca-{}.pem", port));
+        std::fs::write(&ca_path, pem).unwrap();
+
+        let tls = TlsConfig {
+            ca_path: Some(ca_path.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let greeting = read_greeting(port, &tls).await;
+        std::fs::remove_file(&ca_path).unwrap();
+
+        assert_eq!(greeting.unwrap(), "hello");
+    }
+}
@@ -0,0 +1,215 @@
+/// What a unit measures; values convert between units of the same dimension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Data,
+    DataRate,
+    Temperature,
+    Rotation,
+    Power,
+    Voltage,
+    Percent,
+}
+
+/// A unit we know how to convert, `value * scale + offset` gives the value in
+/// the dimension's base unit (bytes, °C, watts...)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Unit {
+    dimension: Dimension,
+    scale: f64,
+    offset: f64,
+}
+
+impl Unit {
+    const fn new(dimension: Dimension, scale: f64) -> Self {
+        Self {
+            dimension,
+            scale,
+            offset: 0.0,
+        }
+    }
+
+    fn base_value(self, value: f64) -> f64 {
+        value * self.scale + self.offset
+    }
+
+    fn in_unit(self, value: f64) -> f64 {
+        (value - self.offset) / self.scale
+    }
+}
+
+const DECIMAL_BYTES: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+const BINARY_BYTES: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+const DECIMAL_BITS: [&str; 5] = ["bit", "Kbit", "Mbit", "Gbit", "Tbit"];
+const BINARY_BITS: [&str; 5] = ["bit", "Kibit", "Mibit", "Gibit", "Tibit"];
+const POWER: [&str; 3] = ["mW", "W", "kW"];
+const VOLTAGE: [&str; 2] = ["mV", "V"];
+
+/// How values are shown, taken from a key's settings
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnitFormat<'a> {
+    /// Unit to show values in; picked from the value's magnitude when unset
+    /// or when it measures something else than the value
+    pub display_unit: Option<&'a str>,
+    /// Pick KiB, MiB... instead of KB, MB...
+    pub binary: bool,
+    /// Pick bits instead of bytes for data
+    pub bits: bool,
+    /// Digits after the decimal point
+    pub precision: usize,
+}
+
+impl UnitFormat<'_> {
+    /// Format a value given in `unit`, converting it to the display unit
+    ///
+    /// Units that aren't recognized are shown as they are, and a value
+    /// without a unit is shown as a plain number.
+    pub fn format(&self, value: f32, unit: Option<&str>) -> String {
+        let unit = unit.map(str::trim).filter(|unit| !unit.is_empty());
+        let Some((unit, known)) = unit.and_then(|unit| Some((unit, parse(unit)?))) else {
+            let suffix = unit.map(unit_suffix).unwrap_or_default();
+            return format!("{:.*}{}", self.precision, value, suffix);
+        };
+
+        let base = known.base_value(value as f64);
+        let (symbol, display) = match self
+            .display_unit
+            .map(str::trim)
+            .and_then(|display| Some((display, parse(display)?)))
+        {
+            Some((symbol, display)) if display.dimension == known.dimension => {
+                (symbol.to_string(), display)
+            }
+            _ => self.pick(base, unit, known),
+        };
+
+        format!(
+            "{:.*}{}",
+            self.precision,
+            display.in_unit(base),
+            unit_suffix(&symbol)
+        )
+    }
+
+    /// Pick the largest unit the value is at least one of, keeping units
+    /// without prefixes (°F, RPM...) as they were reported
+    fn pick(&self, base: f64, unit: &str, known: Unit) -> (String, Unit) {
+        let (candidates, per_second): (&[&str], bool) = match known.dimension {
+            Dimension::Data | Dimension::DataRate => {
+                let candidates: &[&str] = match (self.bits, self.binary) {
+                    (false, false) => &DECIMAL_BYTES,
+                    (false, true) => &BINARY_BYTES,
+                    (true, false) => &DECIMAL_BITS,
+                    (true, true) => &BINARY_BITS,
+                };
+                (candidates, known.dimension == Dimension::DataRate)
+            }
+            Dimension::Power => (&POWER, false),
+            Dimension::Voltage => (&VOLTAGE, false),
+            Dimension::Temperature | Dimension::Rotation | Dimension::Percent => {
+                return (unit.to_string(), known);
+            }
+        };
+
+        let candidates: Vec<(String, Unit)> = candidates
+            .iter()
+            .map(|symbol| {
+                let symbol = if per_second {
+                    format!("{}/s", symbol)
+                } else {
+                    symbol.to_string()
+                };
+                let unit = parse(&symbol).unwrap_or(known);
+                (symbol, unit)
+            })
+            .collect();
+
+        // Zero reads best in the base unit rather than the smallest one
+        let magnitude = base.abs();
+        let picked = if magnitude == 0.0 {
+            candidates.iter().find(|(_, unit)| unit.scale == 1.0)
+        } else {
+            candidates
+                .iter()
+                .rev()
+                .find(|(_, unit)| unit.scale <= magnitude)
+        };
+
+        picked
+            .or(candidates.first())
+            .cloned()
+            .unwrap_or_else(|| (unit.to_string(), known))
+    }
+}
+
+/// Recognize a unit symbol such as `°F`, `kW`, `MiB/s` or `Mbps`
+fn parse(symbol: &str) -> Option<Unit> {
+    let unit = match symbol {
+        "°C" | "C" | "℃" => Unit::new(Dimension::Temperature, 1.0),
+        "°F" | "F" | "℉" => Unit {
+            dimension: Dimension::Temperature,
+            scale: 5.0 / 9.0,
+            offset: -160.0 / 9.0,
+        },
+        "K" => Unit {
+            dimension: Dimension::Temperature,
+            scale: 1.0,
+            offset: -273.15,
+        },
+        "RPM" | "rpm" => Unit::new(Dimension::Rotation, 1.0),
+        "%" => Unit::new(Dimension::Percent, 1.0),
+        "mW" => Unit::new(Dimension::Power, 1e-3),
+        "W" => Unit::new(Dimension::Power, 1.0),
+        "kW" => Unit::new(Dimension::Power, 1e3),
+        "mV" => Unit::new(Dimension::Voltage, 1e-3),
+        "V" => Unit::new(Dimension::Voltage, 1.0),
+        _ => return parse_data(symbol),
+    };
+
+    Some(unit)
+}
+
+/// Recognize data sizes and rates: an optional decimal or binary prefix,
+/// `B` for bytes or `bit`/`b` for bits, and `/s` or `ps` for rates
+fn parse_data(symbol: &str) -> Option<Unit> {
+    let (symbol, dimension) = match symbol
+        .strip_suffix("/s")
+        .or_else(|| symbol.strip_suffix("ps"))
+    {
+        Some(symbol) => (symbol, Dimension::DataRate),
+        None => (symbol, Dimension::Data),
+    };
+
+    let (prefix, size) = if let Some(prefix) = symbol.strip_suffix("bit") {
+        (prefix, 0.125)
+    } else if let Some(prefix) = symbol.strip_suffix('B') {
+        (prefix, 1.0)
+    } else if let Some(prefix) = symbol.strip_suffix('b') {
+        (prefix, 0.125)
+    } else {
+        return None;
+    };
+
+    let multiplier = match prefix {
+        "" => 1.0,
+        "k" | "K" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        "Ki" => 1024.0,
+        "Mi" => 1024.0 * 1024.0,
+        "Gi" => 1024.0 * 1024.0 * 1024.0,
+        "Ti" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+
+    Some(Unit::new(dimension, multiplier * size))
+}
+
+/// Degrees and percentages follow the number directly, other units after a space
+fn unit_suffix(unit: &str) -> String {
+    if unit.starts_with('°') || unit.starts_with('%') {
+        unit.to_string()
+    } else {
+        format!(" {}", unit)
+    }
+}
@@ -1,103 +1,281 @@
 use anyhow::{anyhow, Result};
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{SinkExt, Stream, StreamExt};
+use http::header::SEC_WEBSOCKET_PROTOCOL;
+use lazy_static::lazy_static;
 use serde_json::Value;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use tokio::net::TcpStream;
 use tokio::sync::Mutex;
-use tokio::time::Duration;
-use tokio_tungstenite::tungstenite::http::Request;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio::time::{interval, interval_at, Duration, Instant, Interval, MissedTickBehavior};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Request;
+use tokio_tungstenite::{client_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
 
-/// WebSocket data source configuration
-#[derive(Clone)]
+use crate::auth::AuthConfig;
+use crate::extract::{self, MessageFilter};
+use crate::graph_data::{BinaryFormat, InitStep, WebSocketMode};
+use crate::source::{
+    self, Backoff, ConnectionPool, ConnectionState, InitFailed, SharedState, Subscriber,
+    Subscribers, Subscription, SubscriptionConfig,
+};
+use crate::template::Templates;
+use crate::tls::{self, TlsConfig};
+
+/// Message IDs used for the Home Assistant requests we issue after authenticating
+const HA_GET_STATES_ID: u64 = 1;
+const HA_SUBSCRIBE_ID: u64 = 2;
+
+/// How long an init step waits for its expected response unless configured otherwise
+const INIT_STEP_DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Poll interval when a poll message is set without one, matching the key refresh rate
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keepalive timeout, in ping intervals, when none is configured
+const KEEPALIVE_TIMEOUT_INTERVALS: u32 = 3;
+
+/// How often a Home Assistant connection looks for keys that joined it since
+/// the states were fetched, matching the key refresh rate
+const HA_JOIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref CONNECTIONS: ConnectionPool<WebSocketConfig> = ConnectionPool::new();
+}
+
+/// WebSocket connection configuration
+///
+/// Keys with identical connection settings share a single socket.
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct WebSocketConfig {
     pub url: String,
-    pub api_key: Option<String>,
-    pub init_messages: Vec<String>,
+    pub auth: AuthConfig,
+    pub subprotocols: Vec<String>,
+    pub init_steps: Vec<InitStep>,
+    pub binary_format: BinaryFormat,
+    pub poll_message: Option<String>,
+    pub poll_interval: Option<Duration>,
+    pub keepalive_interval: Option<Duration>,
+    pub keepalive_timeout: Option<Duration>,
+    pub mode: WebSocketMode,
+    pub tls: TlsConfig,
 }
 
-/// WebSocket data source client
-pub struct WebSocketClient {
-    config: WebSocketConfig,
-    current_value: Arc<Mutex<f32>>,
+impl WebSocketConfig {
+    /// Build the handshake request with credentials, extra headers and subprotocols
+    fn handshake_request(&self) -> Result<Request> {
+        // Home Assistant authenticates in-band after connecting
+        let (url, headers) = match self.mode {
+            WebSocketMode::Generic => self.auth.apply(&self.url)?,
+            WebSocketMode::HomeAssistant => self.auth.without_credentials().apply(&self.url)?,
+        };
+
+        let mut request = url.into_client_request()?;
+        request.headers_mut().extend(headers);
+
+        if !self.subprotocols.is_empty() {
+            request.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                self.subprotocols.join(", ").parse()?,
+            );
+        }
+
+        Ok(request)
+    }
+
+    /// Collect the init steps to run, checking that their matchers parse and
+    /// that each step sends or waits for something
+    fn init_plan(&self) -> Result<Vec<InitPlanStep>> {
+        self.init_steps
+            .iter()
+            .enumerate()
+            .map(|(idx, step)| {
+                let filter = |filter: &Option<String>| {
+                    let filter = filter.as_deref().map(str::trim).filter(|f| !f.is_empty());
+                    filter.map(MessageFilter::parse).transpose()?;
+                    Ok::<_, anyhow::Error>(filter.map(str::to_string))
+                };
+                let message = Some(step.message.clone()).filter(|m| !m.trim().is_empty());
+                let expect = filter(&step.expect)?;
+                let fail = filter(&step.fail)?;
+                let wait = step.wait_for_reply || expect.is_some() || fail.is_some();
+                if message.is_none() && !wait {
+                    return Err(anyhow!(
+                        "Init step {} has no message and nothing to wait for",
+                        idx + 1
+                    ));
+                }
+
+                Ok(InitPlanStep {
+                    message,
+                    expect,
+                    fail,
+                    wait,
+                    timeout: step
+                        .timeout_secs
+                        .map(Duration::from_secs)
+                        .unwrap_or(INIT_STEP_DEFAULT_TIMEOUT),
+                })
+            })
+            .collect()
+    }
+
+    /// Open the socket and run the WebSocket handshake, applying the TLS
+    /// settings to wss:// URLs
+    async fn connect(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let request = self.handshake_request()?;
+        let stream = tls::open_stream(request.uri(), &self.tls).await?;
+        let (ws_stream, _) = client_async(request, stream).await?;
+        Ok(ws_stream)
+    }
 }
 
-impl WebSocketClient {
-    pub fn new(config: WebSocketConfig) -> Self {
+/// Periodic poll requests and keepalive pings for an open connection
+struct ConnectionTimers {
+    poll: Option<Poll>,
+    ping: Option<Interval>,
+    dead_after: Duration,
+    /// When any frame (data, ping or pong) last arrived
+    last_seen: Arc<std::sync::Mutex<Instant>>,
+}
+
+struct Poll {
+    interval: Interval,
+    message: String,
+    templates: Templates,
+}
+
+impl ConnectionTimers {
+    /// Polling is only enabled when `templates` are given to render the poll message
+    fn new(config: &WebSocketConfig, templates: Option<Templates>) -> Self {
+        let poll = config
+            .poll_message
+            .clone()
+            .zip(templates)
+            .map(|(message, templates)| {
+                // The first request goes out right away
+                let mut interval = interval(config.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL));
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                Poll {
+                    interval,
+                    message,
+                    templates,
+                }
+            });
+        let ping = config.keepalive_interval.map(|period| {
+            let mut ping = interval_at(Instant::now() + period, period);
+            ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ping
+        });
+        let dead_after = config.keepalive_timeout.unwrap_or_else(|| {
+            config.keepalive_interval.unwrap_or_default() * KEEPALIVE_TIMEOUT_INTERVALS
+        });
+
         Self {
-            config,
-            current_value: Arc::new(Mutex::new(0.0)),
+            poll,
+            ping,
+            dead_after,
+            last_seen: Arc::new(std::sync::Mutex::new(Instant::now())),
         }
     }
 
-    /// Start the WebSocket connection and data fetching loop
-    pub async fn start(&self) -> Result<()> {
-        let config = self.config.clone();
-        let current_value = self.current_value.clone();
+    /// Wrap the read half so every incoming frame counts as a sign of life
+    fn track<S: Stream>(&self, read: S) -> impl Stream<Item = S::Item> {
+        let last_seen = self.last_seen.clone();
+        read.inspect(move |_| *last_seen.lock().unwrap() = Instant::now())
+    }
 
-        tokio::spawn(async move {
-            if let Err(e) = Self::run_connection(config, current_value).await {
-                log::error!("WebSocket connection error: {}", e);
+    /// Wait for the next message to send, failing once the connection has gone quiet
+    async fn next(&mut self) -> Result<Message> {
+        tokio::select! {
+            _ = tick(self.poll.as_mut().map(|poll| &mut poll.interval)) => {
+                let poll = self.poll.as_mut().ok_or_else(|| anyhow!("Polling is disabled"))?;
+                poll.templates.next_message();
+                Ok(Message::Text(poll.templates.render(&poll.message)?))
             }
-        });
+            _ = tick(self.ping.as_mut()) => {
+                let silent = self.last_seen.lock().unwrap().elapsed();
+                if silent > self.dead_after {
+                    return Err(anyhow!(
+                        "No data or pong for {:.0} seconds, connection is dead",
+                        silent.as_secs_f32()
+                    ));
+                }
+                Ok(Message::Ping(Vec::new()))
+            }
+        }
+    }
+}
 
-        Ok(())
+/// Wait for the next tick of an optional interval, forever if there is none
+async fn tick(interval: Option<&mut Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
     }
+}
+
+/// An init step ready to run; the message and matchers are still templates
+struct InitPlanStep {
+    /// Message to send, steps without one only wait
+    message: Option<String>,
+    expect: Option<String>,
+    fail: Option<String>,
+    /// Whether to wait for a reply before the next step
+    wait: bool,
+    timeout: Duration,
+}
+
+/// WebSocket data source client
+///
+/// Connections are shared by every key with the same config.
+pub struct WebSocketClient;
+
+impl WebSocketClient {
+    /// Subscribe to a connection, reusing an open one with the same config if possible
+    pub async fn subscribe(
+        config: WebSocketConfig,
+        subscription: SubscriptionConfig,
+    ) -> Result<Subscription> {
+        // Catch invalid matchers now rather than on every reconnect
+        config.init_plan()?;
 
-    /// Get the current value
-    pub async fn get_value(&self) -> f32 {
-        *self.current_value.lock().await
+        let url = config.url.clone();
+        Ok(CONNECTIONS
+            .subscribe(config.clone(), &url, subscription, |subscribers, state| {
+                Self::run_connection(config, subscribers, state)
+            })
+            .await)
     }
 
-    async fn run_connection(config: WebSocketConfig, current_value: Arc<Mutex<f32>>) -> Result<()> {
+    async fn run_connection(config: WebSocketConfig, subscribers: Subscribers, state: SharedState) {
+        let mut backoff = Backoff::default();
+
         loop {
-            match Self::connect_and_run(&config, &current_value).await {
-                Ok(_) => {
-                    log::info!("WebSocket connection closed, reconnecting in 5 seconds...");
+            let result = match config.mode {
+                WebSocketMode::Generic => {
+                    Self::connect_and_run(&config, &subscribers, &state).await
                 }
-                Err(e) => {
-                    log::error!("WebSocket error: {}, reconnecting in 5 seconds...", e);
+                WebSocketMode::HomeAssistant => {
+                    Self::run_home_assistant(&config, &subscribers, &state).await
                 }
-            }
-            tokio::time::sleep(Duration::from_secs(5)).await;
+            };
+
+            let delay = backoff.finish("WebSocket", &state, result).await;
+            tokio::time::sleep(delay).await;
         }
     }
 
     async fn connect_and_run(
         config: &WebSocketConfig,
-        current_value: &Arc<Mutex<f32>>,
+        subscribers: &Subscribers,
+        state: &SharedState,
     ) -> Result<()> {
         log::info!("Connecting to WebSocket: {}", config.url);
 
-        // Build request with Authorization header if API key is provided
-        let ws_stream = if let Some(api_key) = &config.api_key {
-            let request = Request::builder()
-                .uri(&config.url)
-                .header(
-                    "Host",
-                    config
-                        .url
-                        .split("//")
-                        .nth(1)
-                        .unwrap_or("")
-                        .split('/')
-                        .next()
-                        .unwrap_or(""),
-                )
-                .header("Connection", "Upgrade")
-                .header("Upgrade", "websocket")
-                .header("Sec-WebSocket-Version", "13")
-                .header(
-                    "Sec-WebSocket-Key",
-                    tokio_tungstenite::tungstenite::handshake::client::generate_key(),
-                )
-                .header("Authorization", format!("Bearer {}", api_key))
-                .body(())?;
-            let (ws_stream, _) = connect_async(request).await?;
-            ws_stream
-        } else {
-            let (ws_stream, _) = connect_async(&config.url).await?;
-            ws_stream
-        };
+        let ws_stream = config.connect().await?;
 
         let (write, mut read) = ws_stream.split();
 
@@ -106,45 +284,98 @@ impl WebSocketClient {
 
         log::info!("WebSocket connected, sending initialization messages");
 
-        // Send initialization messages and wait for responses
-        for (idx, init_msg) in config.init_messages.iter().enumerate() {
-            log::debug!("Sending init message {}: {}", idx + 1, init_msg);
-            write
-                .lock()
-                .await
-                .send(Message::Text(init_msg.clone()))
-                .await?;
-
-            // Wait for response
-            if let Some(response) = read.next().await {
-                match response {
-                    Ok(Message::Text(text)) => {
-                        log::debug!("Init response {}: {}", idx + 1, text);
-                    }
-                    Ok(Message::Close(_)) => {
-                        return Err(anyhow!("WebSocket closed during initialization"));
-                    }
-                    Err(e) => {
-                        return Err(anyhow!("Error receiving init response: {}", e));
-                    }
-                    _ => {}
+        let mut templates = Templates::new(config.auth.api_key()?);
+
+        // Send initialization messages and wait for the responses they expect
+        for (idx, step) in config.init_plan()?.iter().enumerate() {
+            // Matchers see the same {{msg_id}} as their message
+            templates.next_message();
+            let matcher = |filter: &Option<String>| {
+                filter
+                    .as_deref()
+                    .map(|filter| MessageFilter::parse(&templates.render(filter)?))
+                    .transpose()
+            };
+            let expect = matcher(&step.expect)?;
+            let fail = matcher(&step.fail)?;
+
+            if let Some(message) = &step.message {
+                let message = templates.render(message)?;
+                log::debug!("Sending init message {}: {}", idx + 1, message);
+                write.lock().await.send(Message::Text(message)).await?;
+            }
+
+            if !step.wait {
+                continue;
+            }
+
+            let deadline = Instant::now() + step.timeout;
+            let no_response = || InitFailed(format!("no response to init message {}", idx + 1));
+
+            // Any text reply completes a step without matchers, JSON or not
+            if expect.is_none() && fail.is_none() {
+                let reply = tokio::time::timeout_at(deadline, Self::next_text(&mut read))
+                    .await
+                    .map_err(|_| no_response())??;
+                log::debug!("Init response {}: {}", idx + 1, reply);
+                continue;
+            }
+
+            loop {
+                let json = tokio::time::timeout_at(deadline, Self::next_json(&mut read))
+                    .await
+                    .map_err(|_| no_response())??;
+
+                if fail.as_ref().is_some_and(|fail| fail.matches(&json)) {
+                    return Err(InitFailed(format!(
+                        "init message {} was rejected: {}",
+                        idx + 1,
+                        json
+                    ))
+                    .into());
+                }
+
+                // Steps with only a failure condition complete on any other reply
+                if expect.as_ref().is_none_or(|expect| expect.matches(&json)) {
+                    log::debug!("Init response {}: {}", idx + 1, json);
+                    break;
                 }
+
+                // Unsolicited message, e.g. an event pushed before init finished
+                source::dispatch(subscribers, &json).await;
             }
         }
 
         log::info!("Initialization complete, starting data loop");
+        *state.lock().await = ConnectionState::Connected;
+
+        let mut timers = ConnectionTimers::new(config, Some(templates));
+        let mut read = timers.track(read);
+
+        loop {
+            let message = tokio::select! {
+                message = read.next() => message,
+                outgoing = timers.next() => {
+                    write.lock().await.send(outgoing?).await?;
+                    continue;
+                }
+            };
+            let Some(message) = message else {
+                break;
+            };
 
-        while let Some(message) = read.next().await {
             match message {
                 Ok(Message::Text(text)) => {
                     log::debug!("Received message: {}", text);
-                    // Try to parse as JSON and extract numeric value
-                    if let Ok(json) = serde_json::from_str::<Value>(&text) {
-                        if let Some(value) = Self::extract_value(&json) {
-                            let mut current = current_value.lock().await;
-                            *current = value;
-                            log::debug!("Updated value to: {}", value);
+                    source::dispatch_text(subscribers, &text).await;
+                }
+                Ok(Message::Binary(data)) if config.binary_format != BinaryFormat::None => {
+                    match extract::decode_binary(config.binary_format, &data) {
+                        Ok(json) => {
+                            log::debug!("Received binary message: {}", json);
+                            source::dispatch(subscribers, &json).await;
                         }
+                        Err(e) => log::debug!("Ignoring undecodable binary message: {}", e),
                     }
                 }
                 Ok(Message::Close(_)) => {
@@ -162,33 +393,223 @@ impl WebSocketClient {
         Ok(())
     }
 
-    /// Extract a numeric value from JSON response
-    /// This is a simple heuristic - looks for first numeric field
-    fn extract_value(json: &Value) -> Option<f32> {
-        match json {
-            Value::Number(n) => n.as_f64().map(|v| v as f32),
-            Value::Object(map) => {
-                // Try common field names first
-                for key in &["value", "data", "result", "temperature", "temp", "load"] {
-                    if let Some(val) = map.get(*key) {
-                        if let Some(num) = Self::extract_value(val) {
-                            return Some(num);
-                        }
+    /// Run a Home Assistant session: authenticate with a long-lived access token,
+    /// fetch the current states and follow `state_changed` events for every
+    /// subscribed entity
+    ///
+    /// The latest state of every entity is kept, so keys that join the open
+    /// connection or switch entities get a value without waiting for the next
+    /// change.
+    async fn run_home_assistant(
+        config: &WebSocketConfig,
+        subscribers: &Subscribers,
+        state: &SharedState,
+    ) -> Result<()> {
+        let token = config
+            .auth
+            .api_key()?
+            .ok_or_else(|| anyhow!("Home Assistant mode requires an access token"))?;
+
+        log::info!("Connecting to Home Assistant: {}", config.url);
+
+        let ws_stream = config.connect().await?;
+        let (mut write, mut read) = ws_stream.split();
+
+        // Home Assistant greets every client with auth_required before accepting auth
+        loop {
+            let json = Self::next_json(&mut read).await?;
+            match json["type"].as_str() {
+                Some("auth_required") => break,
+                other => log::debug!("Ignoring pre-auth message of type {:?}", other),
+            }
+        }
+
+        let auth = serde_json::json!({ "type": "auth", "access_token": token });
+        write.send(Message::Text(auth.to_string())).await?;
+
+        loop {
+            let json = Self::next_json(&mut read).await?;
+            match json["type"].as_str() {
+                Some("auth_ok") => break,
+                Some("auth_invalid") => {
+                    return Err(InitFailed(format!(
+                        "Home Assistant authentication failed: {}",
+                        json["message"].as_str().unwrap_or("invalid token")
+                    ))
+                    .into());
+                }
+                other => log::debug!("Ignoring message of type {:?} during auth", other),
+            }
+        }
+
+        log::info!("Home Assistant authenticated, subscribing to state changes");
+        *state.lock().await = ConnectionState::Connected;
+
+        let get_states = serde_json::json!({ "id": HA_GET_STATES_ID, "type": "get_states" });
+        write.send(Message::Text(get_states.to_string())).await?;
+
+        let subscribe = serde_json::json!({
+            "id": HA_SUBSCRIBE_ID,
+            "type": "subscribe_events",
+            "event_type": "state_changed",
+        });
+        write.send(Message::Text(subscribe.to_string())).await?;
+
+        // Home Assistant pushes state changes, so only keepalive pings apply
+        let mut timers = ConnectionTimers::new(config, None);
+        let mut read = timers.track(read);
+
+        // Unset until get_states answers, keys given their initial state are served
+        let mut states: Option<HashMap<String, Value>> = None;
+        let mut served: Vec<Weak<Subscriber>> = Vec::new();
+        let mut join_check = interval_at(
+            Instant::now() + HA_JOIN_CHECK_INTERVAL,
+            HA_JOIN_CHECK_INTERVAL,
+        );
+        join_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let json = tokio::select! {
+                json = Self::next_json(&mut read) => json?,
+                outgoing = timers.next() => {
+                    write.send(outgoing?).await?;
+                    continue;
+                }
+                _ = join_check.tick() => {
+                    if let Some(states) = &states {
+                        serve_initial_states(subscribers, states, &mut served).await;
                     }
+                    continue;
                 }
-                // If not found, try first numeric value in any field
-                for (_key, val) in map.iter() {
-                    if let Some(num) = Self::extract_value(val) {
-                        return Some(num);
+            };
+
+            match json["type"].as_str() {
+                Some("result") => {
+                    if json["success"].as_bool() != Some(true) {
+                        return Err(anyhow!(
+                            "Home Assistant request {} failed: {}",
+                            json["id"],
+                            json["error"]["message"].as_str().unwrap_or("unknown error")
+                        ));
+                    }
+
+                    if json["id"].as_u64() == Some(HA_GET_STATES_ID) {
+                        let fetched = json["result"]
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|state| {
+                                Some((state["entity_id"].as_str()?.to_string(), state.clone()))
+                            })
+                            .collect();
+                        let states = states.insert(fetched);
+                        served.clear();
+                        serve_initial_states(subscribers, states, &mut served).await;
+                    }
+                }
+                Some("event") => {
+                    let data = &json["event"]["data"];
+                    if let (Some(states), Some(entity_id)) =
+                        (&mut states, data["entity_id"].as_str())
+                    {
+                        match &data["new_state"] {
+                            Value::Null => states.remove(entity_id),
+                            state => states.insert(entity_id.to_string(), state.clone()),
+                        };
+                    }
+
+                    for subscriber in source::live_subscribers(subscribers).await {
+                        if data["entity_id"].as_str() == subscriber.config.entity_id.as_deref() {
+                            apply_ha_state(&subscriber, &data["new_state"]).await;
+                        }
                     }
                 }
-                None
+                _ => {}
+            }
+        }
+    }
+
+    /// Wait for the next text frame and parse it as JSON, skipping anything else
+    async fn next_json<S>(read: &mut S) -> Result<Value>
+    where
+        S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        loop {
+            let text = Self::next_text(read).await?;
+            match serde_json::from_str::<Value>(&text) {
+                Ok(json) => return Ok(json),
+                Err(e) => log::debug!("Ignoring non-JSON message: {}", e),
             }
-            Value::Array(arr) => {
-                // Try first element
-                arr.first().and_then(|v| Self::extract_value(v))
+        }
+    }
+
+    /// Wait for the next text frame, skipping pings and binary frames
+    async fn next_text<S>(read: &mut S) -> Result<String>
+    where
+        S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        while let Some(message) = read.next().await {
+            match message? {
+                Message::Text(text) => {
+                    log::debug!("Received message: {}", text);
+                    return Ok(text);
+                }
+                Message::Close(_) => return Err(anyhow!("WebSocket closed by server")),
+                _ => {}
             }
-            _ => None,
+        }
+
+        Err(anyhow!("WebSocket stream ended"))
+    }
+}
+
+/// Give keys that haven't been served yet the cached state of their entity
+async fn serve_initial_states(
+    subscribers: &Subscribers,
+    states: &HashMap<String, Value>,
+    served: &mut Vec<Weak<Subscriber>>,
+) {
+    served.retain(|subscriber| subscriber.strong_count() > 0);
+
+    for subscriber in source::live_subscribers(subscribers).await {
+        let weak = Arc::downgrade(&subscriber);
+        if served.iter().any(|served| served.ptr_eq(&weak)) {
+            continue;
+        }
+        served.push(weak);
+
+        let entity_id = subscriber.config.entity_id.as_deref().unwrap_or_default();
+        match states.get(entity_id) {
+            Some(state) => apply_ha_state(&subscriber, state).await,
+            None => log::warn!("Home Assistant entity {:?} not found", entity_id),
         }
     }
 }
+
+/// Store the numeric state and unit of a Home Assistant state object
+///
+/// A configured selector is applied to the state object, so attributes such as
+/// `/attributes/current_temperature` can be graphed instead of the state itself.
+async fn apply_ha_state(subscriber: &Subscriber, state: &Value) {
+    // Entity states are always strings, e.g. "21.5", "unavailable" or "unknown"
+    let value = match &subscriber.config.selector {
+        Some(selector) => selector.select_number(state),
+        None => extract::as_number(&state["state"]),
+    };
+    let Some(value) = value else {
+        log::debug!(
+            "Ignoring non-numeric Home Assistant state: {}",
+            state["state"]
+        );
+        return;
+    };
+
+    subscriber
+        .set_unit(
+            state["attributes"]["unit_of_measurement"]
+                .as_str()
+                .map(str::to_string),
+        )
+        .await;
+    subscriber.set_value(value).await;
+}